@@ -0,0 +1,118 @@
+use std::cmp::Ordering;
+
+/// Compares strings in the alphabetical order of a language.
+/// Letters with diacritics sort with their base letter, except where a language treats them
+/// as separate letters (eg Swedish "å", "ä", "ö" after "z"). Ties are broken by diacritics, then case.
+#[derive(Debug, Clone)]
+pub struct Collator {
+    letters_after_z: Vec<char>, // Extra letters at the end of the alphabet, in order
+    letters_after_n: Vec<char>, // Extra letters between "n" and "o"
+    expansions: Vec<(char, &'static str)>, // Letters that sort as several letters
+}
+
+impl Collator {
+    pub fn new(language: &str) -> Self {
+        let language = language.split('-').next().unwrap_or_default().to_lowercase();
+        let letters_after_z = match language.as_str() {
+            "sv" | "fi" => vec!['å', 'ä', 'æ', 'ö', 'ø'],
+            "da" | "nb" | "nn" | "no" => vec!['æ', 'ä', 'ø', 'ö', 'å'],
+            "et" => vec!['õ', 'ä', 'ö', 'ü'],
+            "is" => vec!['þ', 'æ', 'ö'],
+            _ => vec![],
+        };
+        let letters_after_n = match language.as_str() {
+            "es" | "gl" | "ast" => vec!['ñ'],
+            _ => vec![],
+        };
+        let expansions = match language.as_str() {
+            "sv" | "fi" | "da" | "nb" | "nn" | "no" | "is" | "et" => vec![('ß', "ss")],
+            _ => vec![('ß', "ss"), ('æ', "ae"), ('œ', "oe"), ('ø', "o")],
+        };
+        Self {
+            letters_after_z,
+            letters_after_n,
+            expansions,
+        }
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.primary_key(a)
+            .cmp(&self.primary_key(b))
+            .then_with(|| a.to_lowercase().cmp(&b.to_lowercase()))
+            .then_with(|| b.cmp(a)) // Lowercase first
+    }
+
+    /// Letter weights, ignoring case and (unless tailored) diacritics
+    fn primary_key(&self, s: &str) -> Vec<u32> {
+        let mut ret = vec![];
+        for c in s.chars().flat_map(|c| c.to_lowercase()) {
+            if let Some(pos) = self.letters_after_z.iter().position(|x| *x == c) {
+                ret.push(Self::weight('z') + 1 + pos as u32);
+            } else if let Some(pos) = self.letters_after_n.iter().position(|x| *x == c) {
+                ret.push(Self::weight('n') + 1 + pos as u32);
+            } else if let Some((_, expansion)) = self.expansions.iter().find(|(x, _)| *x == c) {
+                expansion.chars().for_each(|c| ret.push(Self::weight(c)));
+            } else {
+                ret.push(Self::weight(Self::base_letter(c)));
+            }
+        }
+        ret
+    }
+
+    fn weight(c: char) -> u32 {
+        (c as u32) * 16 // Leaves room for tailored letters
+    }
+
+    /// Strips diacritics from common Latin letters
+    fn base_letter(c: char) -> char {
+        match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+            'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+            'ď' | 'đ' | 'ð' => 'd',
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+            'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+            'ĥ' | 'ħ' => 'h',
+            'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+            'ĵ' => 'j',
+            'ķ' => 'k',
+            'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+            'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+            'ŕ' | 'ŗ' | 'ř' => 'r',
+            'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => 's',
+            'ţ' | 'ť' | 'ŧ' | 'ț' => 't',
+            'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+            'ŵ' => 'w',
+            'ý' | 'ÿ' | 'ŷ' => 'y',
+            'ź' | 'ż' | 'ž' => 'z',
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_german() {
+        let collator = Collator::new("de");
+        assert_eq!(collator.compare("Ärzte", "Zug"), Ordering::Less);
+        assert_eq!(collator.compare("Straße", "Strasse"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_swedish() {
+        let collator = Collator::new("sv");
+        assert_eq!(collator.compare("Zorn", "Åre"), Ordering::Less);
+        assert_eq!(collator.compare("Åre", "Ängelholm"), Ordering::Less);
+        assert_eq!(collator.compare("Ängelholm", "Örebro"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_case() {
+        let collator = Collator::new("en");
+        assert_eq!(collator.compare("apple", "Banana"), Ordering::Less);
+        assert_eq!(collator.compare("apple", "Apple"), Ordering::Less);
+    }
+}
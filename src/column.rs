@@ -9,6 +9,10 @@ pub enum ColumnType {
     LabelLang(String),
     AliasLang(String),
     Description,
+    DescriptionLang(String), // Language code, or "any"
+    Sitelink(String),        // Site ID, like "dewiki"
+    SitelinkCount,
+    SitelinkList,
     Item,
     Qid,
     Property(String),
@@ -29,6 +33,14 @@ impl ColumnType {
                 .case_insensitive(true)
                 .build()
                 .expect("RE_ALIAS_LANG does not parse");
+            static ref RE_DESCRIPTION_LANG: Regex = RegexBuilder::new(r#"^description/(.+)$"#)
+                .case_insensitive(true)
+                .build()
+                .expect("RE_DESCRIPTION_LANG does not parse");
+            static ref RE_SITELINK: Regex = RegexBuilder::new(r#"^sitelink/(.+)$"#)
+                .case_insensitive(true)
+                .build()
+                .expect("RE_SITELINK does not parse");
             static ref RE_PROPERTY: Regex = Regex::new(r#"^([Pp]\d+)$"#).expect("RE_PROPERTY does not parse");
             static ref RE_PROP_QUAL: Regex =
                 Regex::new(r#"^\s*([Pp]\d+)\s*/\s*([Pp]\d+)\s*$"#).expect("RE_PROP_QUAL does not parse");
@@ -42,6 +54,8 @@ impl ColumnType {
             "description" => return ColumnType::Description,
             "item" => return ColumnType::Item,
             "qid" => return ColumnType::Qid,
+            "sitelinks" => return ColumnType::SitelinkCount,
+            "sitelinks/list" => return ColumnType::SitelinkList,
             _ => {}
         }
         if let Some(caps) = RE_LABEL_LANG.captures(&s) {
@@ -52,6 +66,14 @@ impl ColumnType {
             let ret = caps.get(1).map(|s|s.as_str().to_lowercase()).unwrap_or_default();
             return ColumnType::AliasLang(ret);
         }
+        if let Some(caps) = RE_DESCRIPTION_LANG.captures(&s) {
+            let ret = caps.get(1).map(|s|s.as_str().trim().to_lowercase()).unwrap_or_default();
+            return ColumnType::DescriptionLang(ret);
+        }
+        if let Some(caps) = RE_SITELINK.captures(&s) {
+            let ret = caps.get(1).map(|s|s.as_str().trim().to_lowercase()).unwrap_or_default();
+            return ColumnType::Sitelink(ret);
+        }
         if let Some(caps) = RE_PROPERTY.captures(&s) {
             let ret = caps.get(1).map(|s|s.as_str().to_uppercase()).unwrap_or_default();
             return ColumnType::Property(ret);
@@ -85,6 +107,10 @@ impl ColumnType {
             Self::Qid => "qid".to_string(),
             Self::LabelLang(l) => format!("language:{}", l),
             Self::AliasLang(l) => format!("alias:{}", l),
+            Self::DescriptionLang(l) => format!("desc:{}", l),
+            Self::Sitelink(site) => format!("sitelink:{}", site),
+            Self::SitelinkCount => "sitelinks".to_string(),
+            Self::SitelinkList => "sitelinks_list".to_string(),
             Self::Property(p) => p.to_lowercase(),
             Self::PropertyQualifier((p, q)) => p.to_lowercase() + "_" + &q.to_lowercase(),
             Self::PropertyQualifierValue((p, q, v)) => {
@@ -138,6 +164,14 @@ impl Column {
                     + "/"
                     + &list.get_label_with_fallback(&prop2, None)
             }
+            ColumnType::DescriptionLang(language) => {
+                list.message("column_description_lang", &[language.as_str()])
+            }
+            ColumnType::Sitelink(site) => {
+                list.message("column_sitelink", &[site.as_str()])
+            }
+            ColumnType::SitelinkCount => list.message("column_sitelinks", &[]),
+            ColumnType::SitelinkList => list.message("column_sitelinks_list", &[]),
             _ => self.label.to_owned(), // Fallback
         };
     }
@@ -4,6 +4,109 @@ use anyhow::{Result,anyhow};
 use serde_json::Value;
 use wikibase::EntityTrait;
 
+/// English output messages, used unless config.json has a translation for the wiki or language
+const ENGLISH_MESSAGES: &[(&str, &str)] = &[
+    ("no_value", "No/unknown value"),
+    ("time_millennium", "$1 millennium"),
+    ("time_century", "$1 century"),
+    ("time_decade", "$1s"),
+    ("ordinal", "$1th"), // See Configuration::get_ordinal for the more specific ordinal_* keys
+    ("ordinal_end_1", "$1st"),
+    ("ordinal_end_2", "$1nd"),
+    ("ordinal_end_3", "$1rd"),
+    ("ordinal_end_11", "$1th"),
+    ("ordinal_end_12", "$1th"),
+    ("ordinal_end_13", "$1th"),
+    ("date_day", "$3 $2 $1"), // Year, month name, day
+    ("date_month", "$2 $1"), // Year, month name
+    ("date_bce", "$1 BCE"),
+    ("date_julian", "$1 (Julian)"),
+    ("month_1", "January"),
+    ("month_2", "February"),
+    ("month_3", "March"),
+    ("month_4", "April"),
+    ("month_5", "May"),
+    ("month_6", "June"),
+    ("month_7", "July"),
+    ("month_8", "August"),
+    ("month_9", "September"),
+    ("month_10", "October"),
+    ("month_11", "November"),
+    ("month_12", "December"),
+    ("section_misc", "Misc"),
+    ("shadow_files_notice", "The following local image(s) are not shown in the above list, because they shadow a Commons image of the same name, and might be non-free:"),
+    ("edit_summary", "Wikidata list updated [V2]"),
+    ("summary_itemnumber", "&sum; $1 items."),
+    ("rows_not_shown", "$1 more not shown."),
+    ("pages", "Pages: $1"),
+    ("subpage_unused", "This page is no longer part of the list on [[$1]]."),
+    ("summary_section_count", "$1 items"),
+    ("summary_cell_count", "Count"),
+    ("summary_sum", "Sum"),
+    ("summary_min", "Minimum"),
+    ("summary_max", "Maximum"),
+    ("summary_average", "Average"),
+    ("column_description_lang", "description/$1"), // Language code, or "any"
+    ("column_sitelink", "sitelink/$1"), // Site ID
+    ("column_sitelinks", "sitelinks"),
+    ("column_sitelinks_list", "sitelinks/list"),
+];
+
+/// Entity viewers for links=viewer:name; config.json can add more, or change these
+const DEFAULT_ENTITY_VIEWERS: &[(&str, &str)] = &[
+    ("reasonator", "https://reasonator.toolforge.org/?q=$1"),
+    ("scholia", "https://scholia.toolforge.org/$1"),
+    ("sqid", "https://sqid.toolforge.org/#/view?id=$1"),
+];
+
+/// MediaWiki language fallbacks; config.json can set chains per wiki, or for all wikis as "default"
+const DEFAULT_LANGUAGE_FALLBACKS: &[(&str, &[&str])] = &[
+    ("de-at", &["de"]),
+    ("de-ch", &["de"]),
+    ("de-formal", &["de"]),
+    ("gsw", &["de"]),
+    ("bar", &["de"]),
+    ("ksh", &["de"]),
+    ("lb", &["de"]),
+    ("nds", &["de"]),
+    ("frr", &["de"]),
+    ("li", &["nl"]),
+    ("nds-nl", &["nl"]),
+    ("vls", &["nl"]),
+    ("nl-informal", &["nl"]),
+    ("en-gb", &["en"]),
+    ("en-ca", &["en"]),
+    ("pt-br", &["pt"]),
+    ("pt", &["pt-br"]),
+    ("gl", &["pt"]),
+    ("ast", &["es"]),
+    ("an", &["es"]),
+    ("ca", &["oc"]),
+    ("oc", &["ca", "fr"]),
+    ("wa", &["fr"]),
+    ("frp", &["fr"]),
+    ("co", &["it"]),
+    ("sc", &["it"]),
+    ("scn", &["it"]),
+    ("vec", &["it"]),
+    ("nb", &["no", "nn"]),
+    ("nn", &["no", "nb"]),
+    ("be-tarask", &["be"]),
+    ("uk", &["ru"]),
+    ("sr-ec", &["sr"]),
+    ("sr-el", &["sr"]),
+    ("zh", &["zh-hans"]),
+    ("zh-hans", &["zh"]),
+    ("zh-hant", &["zh-hans", "zh"]),
+    ("zh-cn", &["zh-hans", "zh"]),
+    ("zh-sg", &["zh-hans", "zh"]),
+    ("zh-my", &["zh-hans", "zh"]),
+    ("zh-hk", &["zh-hant", "zh"]),
+    ("zh-mo", &["zh-hk", "zh-hant", "zh"]),
+    ("zh-tw", &["zh-hant", "zh"]),
+    ("yue", &["zh-hk", "zh-hant", "zh"]),
+];
+
 #[derive(Debug, Clone)]
 pub enum NamespaceGroup {
     All,            // All namespaces forbidden
@@ -19,9 +122,83 @@ impl NamespaceGroup {
     }
 }
 
+/// A SPARQL endpoint, with the Authorization header to send to it, if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparqlEndpoint {
+    url: String,
+    auth_header: Option<String>,
+}
+
+impl SparqlEndpoint {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            auth_header: None,
+        }
+    }
+
+    /// Either a URL, or an object with "endpoint" and optional "auth_header"
+    fn new_from_json(j: &Value) -> Option<Self> {
+        if let Some(url) = j.as_str() {
+            return Some(Self::new(url));
+        }
+        Some(Self {
+            url: j["endpoint"].as_str()?.to_string(),
+            auth_header: j["auth_header"].as_str().map(|s| s.to_string()),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn auth_header(&self) -> &Option<String> {
+        &self.auth_header
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SparqlConfig {
+    endpoints: Vec<SparqlEndpoint>, // Main endpoint first, then fallbacks
+    timeout: Option<u64>,           // Seconds
+}
+
+impl SparqlConfig {
+    pub fn new_from_json(j: &Value) -> Result<Self> {
+        let endpoint = SparqlEndpoint::new_from_json(j)
+            .ok_or_else(|| anyhow!("SPARQL configuration without endpoint: {j}"))?;
+        let mut endpoints = vec![endpoint];
+        if let Some(fallbacks) = j["fallbacks"].as_array() {
+            for fallback in fallbacks {
+                match SparqlEndpoint::new_from_json(fallback) {
+                    Some(endpoint) => endpoints.push(endpoint),
+                    None => {
+                        return Err(anyhow!(
+                            "SPARQL fallback endpoint needs to be a URL, or an object with an endpoint: {fallback}"
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            endpoints,
+            timeout: j["timeout"].as_u64(),
+        })
+    }
+
+    pub fn endpoints(&self) -> &Vec<SparqlEndpoint> {
+        &self.endpoints
+    }
+
+    pub fn timeout(&self) -> Option<u64> {
+        self.timeout
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Configuration {
     wb_apis: HashMap<String, Arc<Api>>,
+    sparql_configs: HashMap<String, SparqlConfig>,
     namespace_blocks: HashMap<String, NamespaceGroup>,
     default_api: String,
     prefer_preferred: bool,
@@ -29,6 +206,10 @@ pub struct Configuration {
     template_start_sites: HashMap<String, String>,
     template_end_sites: HashMap<String, String>,
     location_templates: HashMap<String, String>,
+    date_templates: HashMap<String, String>,
+    entity_viewers: HashMap<String, String>, // Name => URL pattern, with $1 for the entity ID
+    messages: HashMap<String, HashMap<String, String>>, // Wiki, language or "default" => message key => text
+    language_fallbacks: HashMap<String, HashMap<String, Vec<String>>>, // Wiki or "default" => language => fallbacks
     shadow_images_check: Vec<String>,
     default_thumbnail_size: Option<u64>,
     location_regions: Vec<String>,
@@ -70,10 +251,20 @@ impl Configuration {
         let oauth2_token = ret.oauth2_token.to_owned();
         if let Some(o) = j["apis"].as_object() {
             for (k, v) in o.iter() {
-                if let (name, Some(url)) = (k.as_str(), v.as_str()) {
-                    let mut api = wikibase::mediawiki::api::Api::new(&url).await?;
-                    api.set_oauth2(&oauth2_token);
-                    ret.wb_apis.insert(name.to_string(), Arc::new(api));
+                // Either just the API URL, or an object with the API URL and SPARQL settings
+                let url = match v.as_str() {
+                    Some(url) => url,
+                    None => match v["url"].as_str() {
+                        Some(url) => url,
+                        None => continue,
+                    },
+                };
+                let mut api = wikibase::mediawiki::api::Api::new(&url).await?;
+                api.set_oauth2(&oauth2_token);
+                ret.wb_apis.insert(k.to_string(), Arc::new(api));
+                if !v["sparql"].is_null() {
+                    let sparql_config = SparqlConfig::new_from_json(&v["sparql"])?;
+                    ret.sparql_configs.insert(k.to_string(), sparql_config);
                 }
             }
         }
@@ -87,6 +278,61 @@ impl Configuration {
             }
         }
 
+        // Date template patterns
+        if let Some(o) = j["date_templates"].as_object() {
+            for (k, v) in o.iter() {
+                if let (k, Some(v)) = (k.as_str(), v.as_str()) {
+                    ret.date_templates.insert(k.to_string(), v.to_string());
+                }
+            }
+        }
+
+        // Entity viewer URL patterns
+        for (name, url) in DEFAULT_ENTITY_VIEWERS {
+            ret.entity_viewers.insert(name.to_string(), url.to_string());
+        }
+        if let Some(o) = j["entity_viewers"].as_object() {
+            for (k, v) in o.iter() {
+                if let Some(v) = v.as_str() {
+                    ret.entity_viewers.insert(k.to_lowercase(), v.to_string());
+                }
+            }
+        }
+
+        // Translated output messages
+        if let Some(o) = j["messages"].as_object() {
+            for (wiki, messages) in o.iter() {
+                if let Some(messages) = messages.as_object() {
+                    let messages = messages
+                        .iter()
+                        .filter_map(|(k, v)| Some((k.to_string(), v.as_str()?.to_string())))
+                        .collect();
+                    ret.messages.insert(wiki.to_string(), messages);
+                }
+            }
+        }
+
+        // Language fallback chains
+        if let Some(o) = j["language_fallbacks"].as_object() {
+            for (wiki, chains) in o.iter() {
+                if let Some(chains) = chains.as_object() {
+                    let chains = chains
+                        .iter()
+                        .filter_map(|(language, fallbacks)| {
+                            let fallbacks = fallbacks
+                                .as_array()?
+                                .iter()
+                                .filter_map(|l| l.as_str())
+                                .map(|l| l.to_lowercase())
+                                .collect();
+                            Some((language.to_lowercase(), fallbacks))
+                        })
+                        .collect();
+                    ret.language_fallbacks.insert(wiki.to_string(), chains);
+                }
+            }
+        }
+
         // Namespace blocks on wikis
         if let Some(o) = j["namespace_blocks"].as_object() {
             for (k, v) in o.iter() {
@@ -206,6 +452,98 @@ impl Configuration {
         }
     }
 
+    /// Returns the date template pattern for the wiki, if dates should not be formatted by the bot
+    pub fn get_date_template(&self, wiki: &str) -> Option<String> {
+        self.date_templates
+            .get(wiki)
+            .or_else(|| self.date_templates.get("default"))
+            .map(|s| s.to_owned())
+    }
+
+    pub fn get_entity_viewer(&self, name: &str) -> Option<&String> {
+        self.entity_viewers.get(name)
+    }
+
+    /// Returns the message for the wiki, falling back to the language, the "default" messages, then English
+    pub fn get_message(&self, wiki: &str, language: &str, key: &str) -> String {
+        self.get_message_variant(wiki, language, &[key])
+    }
+
+    /// Like get_message, but with keys from the most specific to the most general.
+    /// Each source (wiki, language, "default", English) is checked for all keys before the next one,
+    /// so a translation of the general key wins over a more specific English one.
+    fn get_message_variant(&self, wiki: &str, language: &str, keys: &[&str]) -> String {
+        [wiki, language, "default"]
+            .iter()
+            .filter_map(|wiki| {
+                let messages = self.messages.get(*wiki)?;
+                keys.iter().find_map(|key| messages.get(*key))
+            })
+            .next()
+            .map(|s| s.to_owned())
+            .or_else(|| {
+                keys.iter().find_map(|key| {
+                    ENGLISH_MESSAGES
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| v.to_string())
+                })
+            })
+            .unwrap_or_else(|| keys.last().unwrap_or(&"").to_string())
+    }
+
+    /// Ordinal number like "20th". Messages can be given for a single number ("ordinal_1" for "1er"),
+    /// for the last two or the last digit ("ordinal_end_11", "ordinal_end_1"), and for any number ("ordinal").
+    pub fn get_ordinal(&self, wiki: &str, language: &str, n: i64) -> String {
+        let abs = n.abs();
+        let keys = [
+            format!("ordinal_{}", abs),
+            format!("ordinal_end_{}", abs % 100),
+            format!("ordinal_end_{}", abs % 10),
+            "ordinal".to_string(),
+        ];
+        let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+        self.get_message_variant(wiki, language, &keys)
+            .replace("$1", &n.to_string())
+    }
+
+    /// Returns the direct fallbacks of a language, without the language itself or their own fallbacks.
+    /// Fallbacks for the wiki take precedence over the "default" ones from config.json, then the built-in ones.
+    fn get_direct_language_fallbacks(&self, wiki: &str, language: &str) -> Vec<String> {
+        [wiki, "default"]
+            .iter()
+            .filter_map(|wiki| self.language_fallbacks.get(*wiki)?.get(language))
+            .next()
+            .cloned()
+            .or_else(|| {
+                DEFAULT_LANGUAGE_FALLBACKS
+                    .iter()
+                    .find(|(l, _)| *l == language)
+                    .map(|(_, fallbacks)| fallbacks.iter().map(|l| l.to_string()).collect())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the language, its (transitive) fallbacks, then "mul" and the default language.
+    pub fn get_language_fallbacks(&self, wiki: &str, language: &str) -> Vec<String> {
+        let mut ret = vec![language.to_string()];
+        let mut pos = 0;
+        while pos < ret.len() {
+            for l in self.get_direct_language_fallbacks(wiki, &ret[pos]) {
+                if !l.is_empty() && !ret.contains(&l) {
+                    ret.push(l);
+                }
+            }
+            pos += 1;
+        }
+        for l in ["mul", self.default_language.as_str()] {
+            if !l.is_empty() && !ret.iter().any(|x| x == l) {
+                ret.push(l.to_string());
+            }
+        }
+        ret
+    }
+
     pub fn get_template_start_q(&self) -> String {
         self.template_start_q.to_owned()
     }
@@ -241,9 +579,119 @@ impl Configuration {
         self.wb_apis.get(key)
     }
 
+    pub fn get_sparql_config(&self, key: &str) -> Option<&SparqlConfig> {
+        self.sparql_configs.get(key)
+    }
+
     pub fn get_default_wbapi(&self) -> Result<&Arc<Api>> {
         self.wb_apis
             .get(&self.default_api)
             .ok_or_else(|| anyhow!("No default API set in config file"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparql_config_from_string() {
+        let sc = SparqlConfig::new_from_json(&json!("https://query.example.org/sparql")).unwrap();
+        assert_eq!(sc.endpoints(), &vec![SparqlEndpoint::new("https://query.example.org/sparql")]);
+        assert_eq!(sc.endpoints()[0].auth_header(), &None);
+        assert_eq!(sc.timeout(), None);
+    }
+
+    #[test]
+    fn test_sparql_config_from_object() {
+        let j = json!({
+            "endpoint":"https://query.example.org/sparql",
+            "fallbacks":[
+                "https://mirror.example.org/sparql",
+                {"endpoint":"https://other.example.org/sparql","auth_header":"Bearer other"}
+            ],
+            "auth_header":"Bearer secret",
+            "timeout":30
+        });
+        let sc = SparqlConfig::new_from_json(&j).unwrap();
+        let endpoints: Vec<(&str, Option<&str>)> = sc
+            .endpoints()
+            .iter()
+            .map(|endpoint| (endpoint.url(), endpoint.auth_header().as_deref()))
+            .collect();
+        assert_eq!(
+            endpoints,
+            vec![
+                ("https://query.example.org/sparql", Some("Bearer secret")),
+                ("https://mirror.example.org/sparql", None), // The main endpoint's auth header stays there
+                ("https://other.example.org/sparql", Some("Bearer other")),
+            ]
+        );
+        assert_eq!(sc.timeout(), Some(30));
+    }
+
+    #[test]
+    fn test_language_fallbacks_are_resolved_recursively() {
+        let mut config = Configuration::default();
+        config.default_language = "en".to_string();
+        let chains = [
+            ("de-ch".to_string(), vec!["de-at".to_string()]),
+            ("de-at".to_string(), vec!["de".to_string(), "de-ch".to_string()]),
+        ];
+        config
+            .language_fallbacks
+            .insert("default".to_string(), chains.into_iter().collect());
+        assert_eq!(
+            config.get_language_fallbacks("dewiki", "de-ch"),
+            vec!["de-ch", "de-at", "de", "mul", "en"]
+        );
+        assert_eq!(
+            config.get_language_fallbacks("zhwiki", "zh-mo"),
+            vec!["zh-mo", "zh-hk", "zh-hant", "zh", "zh-hans", "mul", "en"]
+        );
+        assert_eq!(config.get_language_fallbacks("enwiki", "en"), vec!["en", "mul"]);
+    }
+
+    fn config_with_messages(messages: Value) -> Configuration {
+        let mut ret = Configuration::default();
+        for (language, messages) in messages.as_object().unwrap() {
+            let messages = messages
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.as_str().unwrap().to_string()))
+                .collect();
+            ret.messages.insert(language.to_string(), messages);
+        }
+        ret
+    }
+
+    #[test]
+    fn test_ordinal_english() {
+        let config = config_with_messages(json!({}));
+        let ordinals: Vec<String> = [1, 2, 3, 4, 11, 12, 13, 21, 22, 101, 111]
+            .iter()
+            .map(|n| config.get_ordinal("enwiki", "en", *n))
+            .collect();
+        assert_eq!(ordinals, vec!["1st", "2nd", "3rd", "4th", "11th", "12th", "13th", "21st", "22nd", "101st", "111th"]);
+    }
+
+    #[test]
+    fn test_ordinal_translated() {
+        let config = config_with_messages(json!({
+            "de":{"ordinal":"$1."},
+            "fr":{"ordinal":"$1e","ordinal_1":"$1er"}
+        }));
+        assert_eq!(config.get_ordinal("dewiki", "de", 1), "1.");
+        assert_eq!(config.get_ordinal("dewiki", "de", 21), "21.");
+        assert_eq!(config.get_ordinal("frwiki", "fr", 1), "1er");
+        assert_eq!(config.get_ordinal("frwiki", "fr", 21), "21e");
+    }
+
+    #[test]
+    fn test_sparql_config_errors() {
+        assert!(SparqlConfig::new_from_json(&json!({"timeout":30})).is_err());
+        assert!(SparqlConfig::new_from_json(&json!({"endpoint":"https://query.example.org/sparql","fallbacks":[1]})).is_err());
+        assert!(SparqlConfig::new_from_json(&json!({"endpoint":"https://query.example.org/sparql","fallbacks":[{"auth_header":"Bearer secret"}]})).is_err());
+    }
+}
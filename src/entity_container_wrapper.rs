@@ -1,5 +1,6 @@
 use crate::listeria_list::ListeriaList;
 use crate::result_cell_part::PartWithReference;
+use crate::result_cell_part::QuantityPart;
 use crate::result_cell_part::ResultCellPart;
 use crate::result_row::ResultRow;
 use crate::sparql_value::SparqlValue;
@@ -197,6 +198,9 @@ impl EntityContainerWrapper {
                 ResultCellPart::ExternalId((property, _id)) => {
                     entities_to_load.push(property.to_owned());
                 }
+                ResultCellPart::Quantity(QuantityPart { unit: Some(unit), .. }) => {
+                    entities_to_load.push(unit.to_owned());
+                }
                 ResultCellPart::SnakList(v) => self
                     .gather_entities_and_external_properties(&v)
                     .iter()
@@ -212,12 +216,20 @@ impl EntityContainerWrapper {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_backend::{MockBackend, MockData};
 
     #[tokio::test]
     async fn test_pickledb() {
+        let ids: Vec<String> = ["Q1","Q2","Q3","Q4","Q5"].iter().map(|s|s.to_string()).collect();
+        let entities: serde_json::Map<String, serde_json::Value> = ids
+            .iter()
+            .map(|id| (id.to_owned(), json!({"type":"item","id":id,"labels":{},"descriptions":{},"aliases":{},"claims":{},"sitelinks":{}})))
+            .collect();
+        let data: HashMap<String, String> = vec![("ENTITIES".to_string(), json!(entities).to_string())].into_iter().collect();
+        let backend = MockBackend::start(MockData::new_from_fixture(&data, "wikidatawiki", "en").unwrap()).await.unwrap();
+
         let mut ecw = EntityContainerWrapper::new();
-        let api = wikibase::mediawiki::api::Api::new("https://www.wikidata.org/w/api.php").await.unwrap();
-        let ids = ["Q1","Q2","Q3","Q4","Q5"].iter().map(|s|s.to_string()).collect();
+        let api = wikibase::mediawiki::api::Api::new(&backend.api_url("wikidatawiki")).await.unwrap();
         ecw.load_entities_max_size(&api, &ids, 2).await.unwrap();
         assert_eq!(ecw.entities.len(),0);
 
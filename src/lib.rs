@@ -3,6 +3,7 @@ extern crate lazy_static;
 #[macro_use]
 extern crate serde_json;
 
+pub mod collation;
 pub mod column;
 pub mod configuration;
 pub mod database_pool;
@@ -11,12 +12,15 @@ pub mod page_element;
 pub mod page_params;
 pub mod wiki_page_result;
 pub mod site_matrix;
+pub mod sort_key;
 pub mod wiki_apis;
 pub mod entity_container_wrapper;
 pub mod listeria_list;
 pub mod listeria_page;
 pub mod listeria_bot;
+pub mod number_format;
 pub mod reference;
+pub mod sparql_results;
 pub mod sparql_value;
 pub mod template;
 pub mod template_params;
@@ -26,6 +30,8 @@ pub mod result_cell;
 pub mod result_cell_part;
 pub mod result_row;
 pub mod renderer;
+#[cfg(test)]
+pub mod mock_backend;
 
 use crate::listeria_list::ListeriaList;
 use crate::listeria_page::ListeriaPage;
@@ -1,16 +1,24 @@
 use crate::entity_container_wrapper::*;
 use crate::page_params::PageParams;
 use crate::result_cell::*;
-use crate::result_cell_part::ResultCellPart;
+use crate::result_cell_part::{PartWithReference, QuantityPart, ResultCellPart};
 use crate::result_row::ResultRow;
+use crate::sort_key::SortKey;
+use crate::sparql_results::SparqlResults;
 use crate::sparql_value::SparqlValue;
 use crate::template::Template;
+use crate::template_params::AggregateFunction;
 use crate::template_params::LinksType;
 use crate::template_params::ReferencesParameter;
+use crate::template_params::RowFilter;
+use crate::template_params::SectionSort;
 use crate::template_params::SectionType;
 use crate::template_params::SortMode;
 use crate::template_params::SortOrder;
+use crate::template_params::SummaryType;
 use crate::template_params::TemplateParams;
+use crate::collation::Collator;
+use crate::number_format::NumberFormat;
 use crate::column::{Column, ColumnType};
 use anyhow::{Result,anyhow};
 use serde_json::Value;
@@ -22,6 +30,12 @@ use wikibase::entity::*;
 use wikibase::mediawiki::api::Api;
 use wikibase::snak::SnakDataType;
 use futures::future::join_all;
+use crate::configuration::{SparqlConfig, SparqlEndpoint};
+
+const DEFAULT_SPARQL_ENDPOINT: &str = "https://wcqs-beta.wmflabs.org/sparql"; // Commons, which has none in its siteinfo
+const SPARQL_ATTEMPTS_PER_ENDPOINT: usize = 2;
+const SPARQL_ACCEPT: &str = "application/sparql-results+json, application/sparql-results+xml;q=0.9, text/tab-separated-values;q=0.8, text/csv;q=0.7";
+const SPARQL_USER_AGENT: &str = concat!("ListeriaBot/", env!("CARGO_PKG_VERSION"), " (https://github.com/magnusmanske/listeria_rs)");
 
 #[derive(Debug, Clone)]
 pub struct ListeriaList {
@@ -34,8 +48,9 @@ pub struct ListeriaList {
     pub ecw: EntityContainerWrapper,
     results: Vec<ResultRow>,
     shadow_files: Vec<String>,
+    rows_not_shown: usize,
     local_page_cache: HashMap<String, bool>,
-    section_id_to_name: HashMap<usize, String>,
+    section_id_to_name: HashMap<Vec<usize>, String>,
     wb_api: Arc<Api>,
     language: String,
     reference_ids: HashSet<String>,
@@ -57,6 +72,7 @@ impl ListeriaList {
             ecw: EntityContainerWrapper::new(),
             results: vec![],
             shadow_files: vec![],
+            rows_not_shown: 0,
             local_page_cache: HashMap::new(),
             section_id_to_name: HashMap::new(),
             wb_api,
@@ -112,8 +128,8 @@ impl ListeriaList {
         self.page_params.local_file_namespace_prefix()
     }
 
-    pub fn section_name(&self, id: usize) -> Option<&String> {
-        self.section_id_to_name.get(&id)
+    pub fn section_name(&self, section_path: &[usize]) -> Option<&String> {
+        self.section_id_to_name.get(section_path)
     }
 
     pub async fn process_template(&mut self) -> Result<()> {
@@ -131,11 +147,13 @@ impl ListeriaList {
         }
 
         self.params = TemplateParams::new_from_params(&template);
-        if let Some(s) = self.get_template_value(&template, "links") {
-            self.params.set_links(LinksType::new_from_string(s.to_string()))
+        if let LinksType::Viewer(viewer) = self.params.links() {
+            if self.page_params.config().get_entity_viewer(viewer).is_none() {
+                return Err(anyhow!("No entity viewer configured for '{viewer}'"));
+            }
         }
-        if let Some(l) = self.get_template_value(&template, "language") {
-            self.language = l.to_lowercase()
+        if let Some(l) = self.params.language() {
+            self.language = l.to_string()
         }
 
         let wikibase = self.params.wikibase();
@@ -147,6 +165,15 @@ impl ListeriaList {
         Ok(())
     }
 
+    /// Alphabetical order for the list language
+    pub fn collator(&self) -> Collator {
+        Collator::new(self.language())
+    }
+
+    pub fn number_format(&self) -> NumberFormat {
+        NumberFormat::new(self.language())
+    }
+
     pub fn language(&self) -> &String {
         &self.language
     }
@@ -250,46 +277,92 @@ impl ListeriaList {
         }
     }
 
-    pub async fn run_sparql_query(&self, sparql: &str) -> Result<Value> {
-        let endpoint = match self
+    /// Returns the SPARQL endpoints to try, in order
+    fn sparql_endpoints(&self) -> Vec<SparqlEndpoint> {
+        if let Some(sparql_config) = self.sparql_config() {
+            if !sparql_config.endpoints().is_empty() {
+                return sparql_config.endpoints().to_owned();
+            }
+        }
+        match self
             .wb_api
             .get_site_info_string("general", "wikibase-sparql")
         {
-            Ok(endpoint) => {
-                // SPARQL service given by site
-                endpoint
-            }
-            _ => {
-                // Override SPARQL service (hardcoded for Commons)
-                "https://wcqs-beta.wmflabs.org/sparql"
+            Ok(endpoint) => vec![SparqlEndpoint::new(&endpoint)], // SPARQL service given by site
+            _ => vec![SparqlEndpoint::new(DEFAULT_SPARQL_ENDPOINT)],
+        }
+    }
+
+    fn sparql_config(&self) -> Option<&SparqlConfig> {
+        self.page_params
+            .config()
+            .get_sparql_config(&self.params.wikibase().to_lowercase())
+    }
+
+    fn sparql_client(&self) -> Result<wikibase::mediawiki::reqwest::Client> {
+        let mut builder = wikibase::mediawiki::reqwest::Client::builder().user_agent(SPARQL_USER_AGENT);
+        if let Some(timeout) = self.sparql_config().and_then(|sc| sc.timeout()) {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+        Ok(builder.build()?)
+    }
+
+    async fn query_sparql_endpoint(
+        &self,
+        client: &wikibase::mediawiki::reqwest::Client,
+        sparql: &str,
+        endpoint: &SparqlEndpoint,
+    ) -> Result<SparqlResults> {
+        let mut request = client
+            .post(endpoint.url())
+            .header("Accept", SPARQL_ACCEPT)
+            .form(&[("query", sparql)]); // The Accept header picks the result format
+        if let Some(auth_header) = endpoint.auth_header() {
+            request = request.header("Authorization", auth_header);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let text = response.text().await?;
+        SparqlResults::new_from_response(&text, &content_type)
+    }
+
+    /// Connection problems, timeouts, server errors and truncated results might go away on retry;
+    /// client errors like a SPARQL syntax error will not
+    fn is_retryable_sparql_error(e: &anyhow::Error) -> bool {
+        match e.downcast_ref::<wikibase::mediawiki::reqwest::Error>() {
+            Some(e) => {
+                e.is_connect()
+                    || e.is_timeout()
+                    || e.is_body()
+                    || e.is_decode()
+                    || e.status().map(|status| status.is_server_error()).unwrap_or(false)
             }
-        };
+            None => true, // Results that cannot be parsed, usually cut off by a WDQS timeout
+        }
+    }
 
-        // SPARQL might need some retries sometimes, bad server or somesuch
-        let mut attempts_left = 2;
-        loop {
-            let ret = self.wb_api.sparql_query_endpoint(sparql, endpoint).await;//.map_err(|e|anyhow!("{e}"))
-            match ret {
-                Ok(ret) => return Ok(ret),
-                Err(e) => { 
-                    match &e {
-                        wikibase::mediawiki::media_wiki_error::MediaWikiError::String(s) => {
-                            if attempts_left>0 && s=="error decoding response body: expected value at line 1 column 1" {
-                                sleep(Duration::from_millis(500)).await;
-                                attempts_left -= 1;
-                                continue;
-                            } else {
-                                if s=="error decoding response body: expected value at line 1 column 1" {
-                                    return Err(anyhow!("SPARQL is probably broken"));
-                                }
-                                return Err(anyhow!("{e}"))
-                            }
-                        },
-                        e => return Err(anyhow!("{e}"))
-                    }
+    pub async fn run_sparql_query(&self, sparql: &str) -> Result<SparqlResults> {
+        let client = self.sparql_client()?;
+        let mut errors = vec![];
+        for endpoint in self.sparql_endpoints() {
+            // SPARQL might need some retries sometimes, bad server or somesuch
+            for attempt in 0..SPARQL_ATTEMPTS_PER_ENDPOINT {
+                if attempt > 0 {
+                    sleep(Duration::from_millis(500)).await;
+                }
+                match self.query_sparql_endpoint(&client, sparql, &endpoint).await {
+                    Ok(ret) => return Ok(ret),
+                    Err(e) if Self::is_retryable_sparql_error(&e) => errors.push(format!("{}: {e}", endpoint.url())),
+                    Err(e) => return Err(anyhow!("SPARQL query failed: {}: {e}", endpoint.url())),
                 }
             }
         }
+        Err(anyhow!("All SPARQL endpoints failed: {}", errors.join("; ")))
     }
 
     async fn expand_sparql_templates(&self, sparql: &mut String) -> Result<()> {
@@ -336,68 +409,43 @@ impl ListeriaList {
         // Return simulated results
         if self.page_params.simulate() {
             match self.page_params.simulated_sparql_results() {
-                Some(json_text) => {
-                    let j = serde_json::from_str(&json_text)?;
-                    return self.parse_sparql(j);
+                Some(text) => {
+                    let results = SparqlResults::new_from_text(&text)?;
+                    return self.parse_sparql(results);
                 }
                 None => {}
             }
         }
 
         self.profile("BEGIN run_query: run_sparql_query");
-        let j = self.run_sparql_query(&sparql).await?;
+        let results = self.run_sparql_query(&sparql).await?;
         self.profile("END run_query: run_sparql_query");
         if self.page_params.simulate() {
-            println!("{}\n{}\n", &sparql, &j);
+            println!("{}\n{:?}\n", &sparql, &results);
         }
-        self.parse_sparql(j)
+        self.parse_sparql(results)
     }
 
-    fn parse_sparql(&mut self, j: Value) -> Result<()> {
+    fn parse_sparql(&mut self, results: SparqlResults) -> Result<()> {
         self.sparql_rows.clear();
         self.sparql_main_variable = None;
 
-        if false {
-            // Use first variable
-            let first_var = match j["head"]["vars"].as_array() {
-                Some(a) => match a.get(0) {
-                    Some(v) => v.as_str().ok_or(anyhow!("Can't parse first variable"))?.to_string(),
-                    None => return Err(anyhow!("Bad SPARQL head.vars")),
-                },
-                None => return Err(anyhow!("Bad SPARQL head.vars")),
-            };
-            self.sparql_main_variable = Some(first_var);
-        } else if let Some(arr) = j["head"]["vars"].as_array() {
-            // Insist on ?item
-            let required_variable_name = "item";
-            for v in arr {
-                if Some(required_variable_name) == v.as_str() {
-                    self.sparql_main_variable = Some(required_variable_name.to_string());
-                    break;
-                }
-            }
+        // The row key variable; ?item unless the template says otherwise
+        let item_var = self.params.item_var();
+        if !results.vars().iter().any(|v| v == item_var) {
+            let available = results
+                .vars()
+                .iter()
+                .map(|v| format!("?{v}"))
+                .collect::<Vec<String>>()
+                .join(", ");
+            return Err(anyhow!(
+                "SPARQL variable ?{item_var} not found in query results; available variables: {available}"
+            ));
         }
+        self.sparql_main_variable = Some(item_var.to_string());
 
-        let bindings = j["results"]["bindings"]
-            .as_array()
-            .ok_or(anyhow!("Broken SPARQL results.bindings"))?;
-        for b in bindings.iter() {
-            let mut row: HashMap<String, SparqlValue> = HashMap::new();
-            if let Some(bo) = b.as_object() {
-                for (k, v) in bo.iter() {
-                    match SparqlValue::new_from_json(&v) {
-                        Some(v2) => row.insert(k.to_owned(), v2),
-                        None => {
-                            return Err(anyhow!("Can't parse SPARQL value: {} => {:?}", &k, &v))
-                        }
-                    };
-                }
-            }
-            if row.is_empty() {
-                continue;
-            }
-            self.sparql_rows.push(row);
-        }
+        self.sparql_rows = results.into_rows();
         Ok(())
     }
 
@@ -714,22 +762,74 @@ impl ListeriaList {
         Ok(())
     }
 
+    async fn process_filter_rows(&mut self) -> Result<()> {
+        if self.params.filters().is_empty() {
+            return Ok(());
+        }
+        // Columns like item or ?field do not need entities, but the filters do
+        self.load_row_entities().await?;
+        let keep: Vec<bool> = self
+            .results
+            .iter()
+            .map(|row| match self.ecw.get_entity(row.entity_id()) {
+                Some(entity) => self
+                    .params
+                    .filters()
+                    .iter()
+                    .all(|filter| self.entity_matches_filter(&entity, filter)),
+                None => false,
+            })
+            .collect();
+        self.results
+            .iter_mut()
+            .zip(keep.into_iter())
+            .for_each(|(row, keep)| row.set_keep(keep));
+        self.results.retain(|row| row.keep());
+        Ok(())
+    }
+
+    fn entity_matches_filter(&self, entity: &wikibase::Entity, filter: &RowFilter) -> bool {
+        match filter {
+            RowFilter::HasProperty(prop) => !self.get_filtered_claims(entity, prop).is_empty(),
+            RowFilter::PropertyValue((prop, item)) => self
+                .get_filtered_claims(entity, prop)
+                .iter()
+                .filter_map(|statement| statement.main_snak().data_value().to_owned())
+                .any(|datavalue| match datavalue.value() {
+                    wikibase::value::Value::Entity(v) => v.id() == item.as_str(),
+                    _ => false,
+                }),
+            RowFilter::Sitelink => match entity.sitelinks() {
+                Some(sl) => sl.iter().any(|s| *s.site() == self.wiki()),
+                None => false,
+            },
+            RowFilter::Label => entity.label_in_locale(&self.language).is_some(),
+            RowFilter::Description => entity.description_in_locale(&self.language).is_some(),
+            RowFilter::Not(filter) => !self.entity_matches_filter(entity, filter),
+        }
+    }
+
+    fn gather_localizable_entity_ids<'a>(parts: &'a [PartWithReference], ids: &mut Vec<&'a String>) {
+        for part in parts {
+            match &part.part {
+                ResultCellPart::Entity((id, true)) => ids.push(id),
+                ResultCellPart::SnakList(v) => Self::gather_localizable_entity_ids(v, ids),
+                _ => {}
+            }
+        }
+    }
+
     async fn process_redlinks(&mut self) -> Result<()> {
         if *self.get_links_type() != LinksType::RedOnly && *self.get_links_type() != LinksType::Red
         {
             return Ok(());
         }
 
-        // Cache if local pages exist
+        // Cache if local pages exist, including items in P/P and P/Q/P cells
         let mut ids = vec![];
         self.results.iter().for_each(|row| {
             row.cells().iter().for_each(|cell| {
-                cell.parts().iter().for_each(|part| {
-                    if let ResultCellPart::Entity((id, true)) = &part.part {
-                        // _try_localize ?
-                        ids.push(id);
-                    }
-                })
+                Self::gather_localizable_entity_ids(cell.parts(), &mut ids);
             });
         });
 
@@ -738,8 +838,8 @@ impl ListeriaList {
         let mut labels = vec![];
         for id in ids {
             if let Some(e) = self.get_entity(id) {
-                if let Some(l) = e.label_in_locale(self.language()) {
-                    labels.push(l.to_string());
+                if let Some(l) = self.get_label_in_fallback_languages(&e, self.language()) {
+                    labels.push(l);
                 }
             }
         }
@@ -760,58 +860,95 @@ impl ListeriaList {
     }
 
     async fn process_sort_results(&mut self) -> Result<()> {
-        let sortkeys: Vec<String>;
-        let mut datatype = SnakDataType::String; // Default
-        match &self.params.sort() {
+        let criteria = self.params.sort().to_owned();
+        if criteria.is_empty() {
+            return Ok(());
+        }
+        let mut sortkeys: Vec<Vec<SortKey>> = vec![vec![]; self.results.len()];
+        for criterion in criteria.iter() {
+            let criterion_sortkeys = self.get_sortkeys(&criterion.mode).await?;
+            if self.results.len() != criterion_sortkeys.len() {
+                // Paranoia
+                return Err(anyhow!("process_sort_results: sortkeys length mismatch"));
+            }
+            sortkeys
+                .iter_mut()
+                .zip(criterion_sortkeys.into_iter())
+                .for_each(|(row_sortkeys, sortkey)| row_sortkeys.push(sortkey));
+        }
+
+        // Apply sortkeys
+        self.results
+            .iter_mut()
+            .zip(sortkeys.into_iter())
+            .for_each(|(row, row_sortkeys)| row.set_sortkeys(row_sortkeys));
+
+        // Stable sort, so rows that compare equal on all keys keep the query order
+        let orders: Vec<SortOrder> = criteria.iter().map(|c| c.order.to_owned()).collect();
+        let collator = self.collator();
+        self.results.sort_by(|a, b| a.compare_to(b, &orders, &collator));
+
+        Ok(())
+    }
+
+    /// Returns the sortkey of each row, for a single sort key
+    async fn get_sortkeys(&mut self, mode: &SortMode) -> Result<Vec<SortKey>> {
+        let sortkeys = match mode {
             SortMode::Label => {
                 self.load_row_entities().await?;
-                sortkeys = self
-                    .results
+                self.results
                     .iter()
                     .map(|row| row.get_sortkey_label(&self))
-                    .collect();
-            }
-            SortMode::FamilyName => {
-                sortkeys = self
-                    .results
-                    .iter()
-                    .map(|row| row.get_sortkey_family_name(&self))
-                    .collect();
+                    .collect()
             }
+            SortMode::FamilyName => self
+                .results
+                .iter()
+                .map(|row| row.get_sortkey_family_name(&self))
+                .collect(),
             SortMode::Property(prop) => {
-                datatype = self.ecw.get_datatype_for_property(prop);
-                sortkeys = self
-                    .results
+                let datatype = self.ecw.get_datatype_for_property(prop);
+                self.results
                     .iter()
                     .map(|row| row.get_sortkey_prop(&prop, &self, &datatype))
-                    .collect();
+                    .collect()
             }
-            SortMode::SparqlVariable(variable) => {
-                sortkeys = self
-                    .results
+            SortMode::PropertyQualifier((prop, qualifier)) => {
+                let datatype = self.ecw.get_datatype_for_property(qualifier);
+                self.results
                     .iter()
-                    .map(|row| row.get_sortkey_sparql(&variable, &self))
-                    .collect();
+                    .map(|row| row.get_sortkey_prop_qual(prop, None, qualifier, &self, &datatype))
+                    .collect()
             }
-            SortMode::None => return Ok(()),
-        }
-
-        // Apply sortkeys
-        if self.results.len() != sortkeys.len() {
-            // Paranoia
-            return Err(anyhow!("process_sort_results: sortkeys length mismatch"));
-        }
-        self.results
-            .iter_mut()
-            .enumerate()
-            .for_each(|(rownum, row)| row.set_sortkey(sortkeys[rownum].to_owned()));
-
-        self.results.sort_by(|a, b| a.compare_to(b, &datatype));
-        if *self.params.sort_order() == SortOrder::Descending {
-            self.results.reverse()
-        }
-
-        Ok(())
+            SortMode::PropertyQualifierValue((prop, target_item, qualifier)) => {
+                let datatype = self.ecw.get_datatype_for_property(qualifier);
+                self.results
+                    .iter()
+                    .map(|row| row.get_sortkey_prop_qual(prop, Some(target_item.as_str()), qualifier, &self, &datatype))
+                    .collect()
+            }
+            SortMode::SparqlVariable(variable) => self
+                .results
+                .iter()
+                .map(|row| row.get_sortkey_sparql(&variable, &self))
+                .collect(),
+            SortMode::SitelinkCount => {
+                self.load_row_entities().await?;
+                self.results
+                    .iter()
+                    .map(|row| row.get_sortkey_sitelink_count(&self))
+                    .collect()
+            }
+            SortMode::Sitelink(site) => {
+                self.load_row_entities().await?;
+                self.results
+                    .iter()
+                    .map(|row| row.get_sortkey_sitelink(site, &self))
+                    .collect()
+            }
+            SortMode::None => vec![SortKey::None; self.results.len()],
+        };
+        Ok(sortkeys)
     }
 
     async fn load_row_entities(&mut self) -> Result<()> {
@@ -826,49 +963,84 @@ impl ListeriaList {
     }
 
     pub async fn process_assign_sections(&mut self) -> Result<()> {
-        // TODO all SectionType options
-        let section_property = match self.params.section() {
-            SectionType::Property(p) => p,
-            SectionType::SparqlVariable(_v) => {
-                return Err(anyhow!("SPARQL variable section type not supported yet"))
-            }
-            SectionType::None => return Ok(()), // Nothing to do
-        }.to_owned();
-        self.load_row_entities().await?;
-        let datatype = self.ecw.get_datatype_for_property(&section_property);
+        let levels = self.params.section().levels();
+        if levels.is_empty() {
+            return Ok(()); // Nothing to do
+        }
+        let mut level_names = vec![];
+        for level in levels.iter() {
+            level_names.push(match level {
+                SectionType::Property(p) => self.get_section_names_for_property(p).await?,
+                SectionType::SparqlVariable(v) => self.get_section_names_for_sparql_variable(v).await?,
+                SectionType::Nested(_) | SectionType::None => continue, // Not a single level
+            });
+        }
 
-        let section_names = self
-            .results
-            .iter()
-            .map(|row| row.get_sortkey_prop(&section_property, self, &datatype))
-            .collect::<Vec<String>>();
+        let rows: Vec<usize> = (0..self.results.len()).collect();
+        let mut section_paths: Vec<Vec<usize>> = vec![vec![]; self.results.len()];
+        let mut section_id_to_name = HashMap::new();
+        self.assign_sections_for_level(&level_names, &[], &rows, &mut section_paths, &mut section_id_to_name);
+        self.section_id_to_name = section_id_to_name;
 
-        // Make sure section name items are loaded
-        self.ecw.load_entities(&self.wb_api, &section_names).await.map_err(|e|anyhow!("{e}"))?;
-        let section_names = section_names
-        .iter()
-        .map(|q| self.get_label_with_fallback(q,None))
-        .collect::<Vec<String>>();
+        self.results
+            .iter_mut()
+            .zip(section_paths.into_iter())
+            .for_each(|(row, section_path)| row.set_section(section_path));
+
+        Ok(())
+    }
+
+    /// Groups rows into the sections of the first level in `level_names`, then does the same for each section with the remaining levels
+    fn assign_sections_for_level(
+        &self,
+        level_names: &[(Vec<String>, HashMap<String, String>)],
+        parent: &[usize],
+        rows: &[usize],
+        section_paths: &mut [Vec<usize>],
+        section_id_to_name: &mut HashMap<Vec<usize>, String>,
+    ) {
+        let (section_names, section_items) = match level_names.first() {
+            Some(x) => x,
+            None => return,
+        };
 
         // Count names
         let mut section_count = HashMap::new();
-        section_names.iter().for_each(|name| {
-            let counter = section_count.entry(name).or_insert(0);
+        rows.iter().for_each(|row| {
+            let counter = section_count.entry(&section_names[*row]).or_insert(0);
             *counter += 1;
         });
 
-        // Remove low counts
-        section_count.retain(|&_name, &mut count| count >= self.params.min_section());
+        // Remove low counts, and rows without a value
+        section_count.retain(|&name, &mut count| {
+            !name.is_empty() && count >= self.params.min_section()
+        });
+
+        // Only split a section further if there is something to split it into
+        if !parent.is_empty() && section_count.is_empty() {
+            return;
+        }
 
-        // Sort by section name
         let mut valid_section_names: Vec<String> =
             section_count.iter().map(|(k, _v)| k.to_string()).collect();
-        valid_section_names.sort();
+        let collator = self.collator();
+        valid_section_names.sort_by(|a, b| collator.compare(a, b));
+        match self.params.section_sort() {
+            SectionSort::Alphabetical => {}
+            SectionSort::Count => {
+                valid_section_names.sort_by(|a, b| section_count[b].cmp(&section_count[a]));
+            }
+            SectionSort::FirstAppearance => {
+                valid_section_names
+                    .sort_by_key(|name| rows.iter().position(|row| section_names[*row] == *name));
+            }
+            SectionSort::Property(prop) => {
+                self.sort_section_names_by_property(&mut valid_section_names, section_items, prop);
+            }
+        }
 
         let misc_id = valid_section_names.len();
-        valid_section_names.push("Misc".to_string());
-
-        // TODO skip if no/one section?
+        valid_section_names.push(self.section_misc());
 
         // name to id
         let name2id: HashMap<String, usize> = valid_section_names
@@ -877,24 +1049,133 @@ impl ListeriaList {
             .map(|(num, name)| (name.to_string(), num))
             .collect();
 
-        self.section_id_to_name = name2id
+        valid_section_names
             .iter()
-            .map(|x| (x.1.to_owned(), x.0.to_owned()))
-            .collect();
+            .enumerate()
+            .for_each(|(id, name)| {
+                let mut section_path = parent.to_vec();
+                section_path.push(id);
+                section_id_to_name.insert(section_path, name.to_owned());
+            });
 
-        self.results.iter_mut().enumerate().for_each(|(num, row)| {
-            let section_name = match section_names.get(num) {
-                Some(name) => name,
-                None => return,
-            };
-            let section_id = match name2id.get(section_name) {
+        let mut section_rows: Vec<Vec<usize>> = vec![vec![]; valid_section_names.len()];
+        rows.iter().for_each(|row| {
+            let section_id = match name2id.get(&section_names[*row]) {
                 Some(id) => *id,
                 None => misc_id,
             };
-            row.set_section(section_id);
+            section_paths[*row].push(section_id);
+            section_rows[section_id].push(*row);
         });
 
-        Ok(())
+        // Leftovers are not split up further
+        section_rows
+            .iter()
+            .enumerate()
+            .filter(|(section_id, _rows)| *section_id != misc_id)
+            .for_each(|(section_id, rows)| {
+                let mut section_path = parent.to_vec();
+                section_path.push(section_id);
+                self.assign_sections_for_level(&level_names[1..], &section_path, rows, section_paths, section_id_to_name);
+            });
+    }
+
+    /// Sorts section names by a property of their section items; sections without a value go last
+    fn sort_section_names_by_property(
+        &self,
+        section_names: &mut [String],
+        section_items: &HashMap<String, String>,
+        prop: &str,
+    ) {
+        let datatype = self.ecw.get_datatype_for_property(prop);
+        let sortkeys: HashMap<String, SortKey> = section_names
+            .iter()
+            .map(|name| {
+                let sortkey = match section_items.get(name) {
+                    Some(item) => ResultRow::new(item).get_sortkey_prop(prop, self, &datatype),
+                    None => SortKey::None,
+                };
+                match sortkey.is_empty() {
+                    true => (name.to_owned(), SortKey::None),
+                    false => (name.to_owned(), sortkey),
+                }
+            })
+            .collect();
+        let collator = self.collator();
+        section_names.sort_by(|a, b| sortkeys[a].compare(&sortkeys[b], &collator));
+    }
+
+    /// Returns the section name for each row, and the section item for each section name, if any
+    async fn get_section_names_for_property(
+        &mut self,
+        section_property: &str,
+    ) -> Result<(Vec<String>, HashMap<String, String>)> {
+        self.load_row_entities().await?;
+
+        // Section name items have been loaded by gather_and_load_items
+        let section_names: Vec<String> = self
+            .results
+            .iter()
+            .map(|row| row.get_section_name_prop(section_property, self))
+            .collect();
+
+        let section_items = self
+            .results
+            .iter()
+            .zip(section_names.iter())
+            .filter_map(|(row, name)| {
+                row.get_item_id_prop(section_property, self)
+                    .map(|item| (name.to_owned(), item))
+            })
+            .collect();
+        Ok((section_names, section_items))
+    }
+
+    /// Returns the section name for each row, and the section item for each section name, if any
+    async fn get_section_names_for_sparql_variable(
+        &mut self,
+        variable: &str,
+    ) -> Result<(Vec<String>, HashMap<String, String>)> {
+        // Make sure section name items are loaded
+        let items_to_load = self.gather_items_for_sparql_variable(variable);
+        self.ecw.load_entities(&self.wb_api, &items_to_load).await.map_err(|e|anyhow!("{e}"))?;
+        let mut section_items = HashMap::new();
+        let section_names = self
+            .results
+            .iter()
+            .map(|row| match row.sparql_value(variable) {
+                Some(value) => {
+                    let name = self.get_section_name_for_sparql_value(value);
+                    if let SparqlValue::Entity(id) = value {
+                        section_items.insert(name.to_owned(), id.to_owned());
+                    }
+                    name
+                }
+                None => String::new(),
+            })
+            .collect();
+        Ok((section_names, section_items))
+    }
+
+    fn get_section_name_for_sparql_value(&self, value: &SparqlValue) -> String {
+        match value {
+            SparqlValue::Entity(id) => self.get_label_with_fallback(id, None),
+            SparqlValue::File(s) | SparqlValue::Uri(s) | SparqlValue::Time(s) | SparqlValue::Literal(s) => s.to_owned(),
+            SparqlValue::Location(loc) => format!("{}/{}", loc.lat, loc.lon),
+            SparqlValue::Number((lexical, _value)) => lexical.to_owned(),
+            SparqlValue::Boolean(b) => b.to_string(),
+            SparqlValue::LangLiteral((text, _language)) => text.to_owned(),
+        }
+    }
+
+    fn gather_items_for_sparql_variable(&self, variable: &str) -> Vec<String> {
+        self.results
+            .iter()
+            .filter_map(|row| match row.sparql_value(variable) {
+                Some(SparqlValue::Entity(id)) => Some(id.to_owned()),
+                _ => None,
+            })
+            .collect()
     }
 
     async fn get_region_for_entity_id(&self, entity_id: &str) -> Option<String> {
@@ -902,28 +1183,19 @@ impl ListeriaList {
             "SELECT ?q ?x {{ wd:{} wdt:P131* ?q . ?q wdt:P300 ?x }}",
             entity_id
         );
-        let j = self.run_sparql_query(&sparql).await.ok()?;
-        match j["results"]["bindings"].as_array() {
-            Some(a) => {
-                let mut region = String::new();
-                a.iter().for_each(|b| {
-                    match b["x"]["type"].as_str() {
-                        Some("literal") => {}
-                        _ => return,
-                    }
-                    if let Some(r) = b["x"]["value"].as_str() {
-                        if r.len() > region.len() {
-                            region = r.to_string();
-                        }
-                    }
-                });
-                if region.is_empty() {
-                    None
-                } else {
-                    Some(region)
+        let results = self.run_sparql_query(&sparql).await.ok()?;
+        let mut region = String::new();
+        results.rows().iter().for_each(|row| {
+            if let Some(SparqlValue::Literal(r)) = row.get("x") {
+                if r.len() > region.len() {
+                    region = r.to_string();
                 }
             }
-            None => None,
+        });
+        if region.is_empty() {
+            None
+        } else {
+            Some(region)
         }
     }
 
@@ -1029,12 +1301,24 @@ impl ListeriaList {
         Ok(())
     }
 
+    /// Drops sorted rows beyond the limit, remembering how many were dropped
+    fn process_limit(&mut self) {
+        if let Some(limit) = self.params.limit() {
+            if self.results.len() > limit {
+                self.rows_not_shown = self.results.len() - limit;
+                self.results.truncate(limit);
+            }
+        }
+    }
+
     pub async fn process_results(&mut self) -> Result<()> {
         self.profile("START list::process_results");
         self.gather_and_load_items().await?;
         self.profile("AFTER list::process_results gather_and_load_items");
         self.process_redlinks_only()?;
         self.profile("AFTER list::process_results process_redlinks_only");
+        self.process_filter_rows().await?;
+        self.profile("AFTER list::process_results process_filter_rows");
         self.process_items_to_local_links()?;
         self.profile("AFTER list::process_results process_items_to_local_links");
         self.process_redlinks().await?;
@@ -1047,6 +1331,8 @@ impl ListeriaList {
         self.profile("AFTER list::process_results process_reference_items");
         self.process_sort_results().await?;
         self.profile("AFTER list::process_results process_sort_results");
+        self.process_limit();
+        self.profile("AFTER list::process_results process_limit");
         self.process_assign_sections().await?;
         self.profile("AFTER list::process_results process_assign_sections");
         self.process_regions().await?;
@@ -1095,24 +1381,57 @@ impl ListeriaList {
         Ok(entities_to_load)
     }
 
+    fn gather_items_for_qualifier(&mut self, prop: &str, qualifier: &str) -> Result<Vec<String>> {
+        let mut entities_to_load = vec![];
+        for row in self.results.iter() {
+            if let Some(entity) = self.ecw.get_entity(row.entity_id()) {
+                self
+                    .get_filtered_claims(&entity, prop)
+                    .iter()
+                    .flat_map(|statement| statement.qualifiers().to_owned())
+                    .filter(|snak| *snak.property() == *qualifier)
+                    .filter(|snak| *snak.datatype() == SnakDataType::WikibaseItem)
+                    .filter_map(|snak| snak.data_value().to_owned())
+                    .filter_map(|datavalue| match datavalue.value() {
+                        wikibase::value::Value::Entity(v) => Some(v.id().to_owned()),
+                        _ => None,
+                    })
+                    .for_each(|id| entities_to_load.push(id));
+            }
+        }
+        Ok(entities_to_load)
+    }
+
     fn gather_items_section(&mut self) -> Result<Vec<String>> {
-        // TODO support all of SectionType
-        let prop = match self.params.section() {
-            SectionType::Property(p) => p.clone(),
-            SectionType::SparqlVariable(_v) => {
-                return Err(anyhow!("SPARQL variable section type not supported yet"))
+        let mut ret = vec![];
+        for level in self.params.section().levels() {
+            match level {
+                SectionType::Property(p) => ret.append(&mut self.gather_items_for_property(&p)?),
+                SectionType::SparqlVariable(v) => ret.append(&mut self.gather_items_for_sparql_variable(&v)),
+                SectionType::Nested(_) | SectionType::None => {} // Not a single level
             }
-            SectionType::None => return Ok(vec![]), // Nothing to do
-        };
-        self.gather_items_for_property(&prop)
+        }
+        Ok(ret)
     }
 
     fn gather_items_sort(&mut self) -> Result<Vec<String>> {
-        let prop = match self.params.sort() {
-            SortMode::Property(prop) => prop.clone(),
-            _ => return Ok(vec![]),
-        };
-        self.gather_items_for_property(&prop)
+        let mut ret = vec![];
+        for criterion in self.params.sort().to_owned() {
+            match criterion.mode {
+                SortMode::Property(prop) => ret.append(&mut self.gather_items_for_property(&prop)?),
+                SortMode::PropertyQualifier((prop, qualifier))
+                | SortMode::PropertyQualifierValue((prop, _, qualifier)) => {
+                    ret.append(&mut self.gather_items_for_qualifier(&prop, &qualifier)?)
+                }
+                SortMode::FamilyName => {
+                    // Family name and given name items
+                    ret.append(&mut self.gather_items_for_property("P734")?);
+                    ret.append(&mut self.gather_items_for_property("P735")?);
+                }
+                _ => {}
+            }
+        }
+        Ok(ret)
     }
 
     async fn gather_and_load_items(&mut self) -> Result<()> {
@@ -1126,18 +1445,24 @@ impl ListeriaList {
                     .for_each(|entity_id| entities_to_load.push(entity_id.to_string()));
             }
         }
-        if let SortMode::Property(prop) = self.params.sort() {
-            entities_to_load.push(prop.to_string());
+        for criterion in self.params.sort() {
+            match &criterion.mode {
+                SortMode::Property(prop) => entities_to_load.push(prop.to_string()),
+                SortMode::PropertyQualifier((_, qualifier))
+                | SortMode::PropertyQualifierValue((_, _, qualifier)) => {
+                    entities_to_load.push(qualifier.to_string())
+                }
+                _ => {}
+            }
         }
 
-        match self.params.section() {
-            SectionType::Property(prop) => {
-                entities_to_load.push(prop.to_owned());
-            }
-            SectionType::SparqlVariable(_v) => {
-                return Err(anyhow!("SPARQL variable section type not supported yet"))
+        for level in self.params.section().levels() {
+            if let SectionType::Property(prop) = level {
+                entities_to_load.push(prop);
             }
-            SectionType::None => {}
+        }
+        if let SectionSort::Property(prop) = self.params.section_sort() {
+            entities_to_load.push(prop.to_string());
         }
         self.ecw
             .load_entities(&self.wb_api, &entities_to_load)
@@ -1163,13 +1488,57 @@ impl ListeriaList {
         self.params.skip_table()
     }
 
-    pub fn get_section_ids(&self) -> Vec<usize> {
-        let mut ret: Vec<usize> = self.results.iter().map(|row| row.section()).collect();
+    /// Section ids of the rows on one page, see `row_range_for_page`
+    pub fn get_section_ids(&self, page: usize) -> Vec<Vec<usize>> {
+        let mut ret: Vec<Vec<usize>> = self.results[self.row_range_for_page(page)]
+            .iter()
+            .map(|row| row.section().to_owned())
+            .collect();
         ret.sort_unstable();
         ret.dedup();
         ret
     }
 
+    /// Name of the section for rows that fit no other section
+    pub fn section_misc(&self) -> String {
+        match self.params.section_misc() {
+            Some(name) => name.to_string(),
+            None => self.message("section_misc", &[]),
+        }
+    }
+
+    pub fn rows_not_shown(&self) -> usize {
+        self.rows_not_shown
+    }
+
+    /// Number of pages the rows are split across; 1 unless per_page is set
+    pub fn number_of_pages(&self) -> usize {
+        match self.params.per_page() {
+            Some(per_page) => self.results.len().div_ceil(per_page).max(1),
+            None => 1,
+        }
+    }
+
+    /// Rows on a page; page 0 is the list page itself, the others are subpages
+    pub fn row_range_for_page(&self, page: usize) -> std::ops::Range<usize> {
+        match self.params.per_page() {
+            Some(per_page) => {
+                let start = std::cmp::min(page * per_page, self.results.len());
+                let end = std::cmp::min(start + per_page, self.results.len());
+                start..end
+            }
+            None => 0..self.results.len(),
+        }
+    }
+
+    /// Title of a page of the list; subpages are numbered from 2, like "List/2"
+    pub fn title_for_page(&self, page: usize) -> String {
+        match page {
+            0 => self.page_title().to_string(),
+            page => format!("{}/{}", self.page_title(), page + 1),
+        }
+    }
+
     pub fn wiki(&self) -> &str {
         self.page_params.wiki()
     }
@@ -1178,10 +1547,93 @@ impl ListeriaList {
         self.page_params.page()
     }
 
-    pub fn summary(&self) -> &Option<String> {
+    pub fn summary(&self) -> &Vec<SummaryType> {
         self.params.summary()
     }
 
+    /// Returns the message for this wiki, with $1, $2 etc. replaced by `args`
+    pub fn message(&self, key: &str, args: &[&str]) -> String {
+        let mut ret = self.page_params.config().get_message(self.wiki(), self.language(), key);
+        for (num, arg) in args.iter().enumerate() {
+            ret = ret.replace(&format!("${}", num + 1), arg);
+        }
+        ret
+    }
+
+    /// Ordinal number like "20th", through the "ordinal" messages
+    pub fn ordinal(&self, n: i64) -> String {
+        self.page_params.config().get_ordinal(self.wiki(), self.language(), n)
+    }
+
+    /// Unit symbol (P5061) in the page language or English, otherwise the label of the unit item
+    pub fn get_unit_symbol(&self, unit: &str) -> String {
+        if let Some(entity) = self.get_entity(unit) {
+            let symbols: Vec<(String, String)> = self
+                .get_filtered_claims(&entity, "P5061")
+                .iter()
+                .filter_map(|statement| match statement.main_snak().data_value() {
+                    Some(dv) => match dv.value() {
+                        wikibase::value::Value::MonoLingual(m) => {
+                            Some((m.language().to_owned(), m.text().to_owned()))
+                        }
+                        _ => None,
+                    },
+                    None => None,
+                })
+                .collect();
+            for language in [self.language().as_str(), "en", "mul"] {
+                if let Some((_, symbol)) = symbols.iter().find(|(l, _)| l == language) {
+                    return symbol.to_owned();
+                }
+            }
+        }
+        self.get_label_with_fallback(unit, None)
+    }
+
+    /// External link to the entity in a configured viewer; a Wikidata link if there is no such viewer
+    pub fn entity_viewer_link(&self, viewer: &str, id: &str, label: &str) -> String {
+        match self.page_params.config().get_entity_viewer(viewer) {
+            Some(url) => format!("[{} {}]", url.replace("$1", id), label),
+            None => format!("[[{}|{}]]", self.get_item_wiki_target(id), label),
+        }
+    }
+
+    pub fn get_date_template(&self) -> Option<String> {
+        self.page_params.config().get_date_template(self.wiki())
+    }
+
+    /// Aggregates the quantities of `prop` over rows; None if no row has a quantity,
+    /// or if the quantities are in different units
+    pub fn aggregate(&self, function: &AggregateFunction, prop: &str, rows: &[&ResultRow]) -> Option<f64> {
+        let quantities: Vec<QuantityPart> = rows
+            .iter()
+            .filter_map(|row| {
+                let entity = self.get_entity(row.entity_id())?;
+                let statement = self.get_filtered_claims(&entity, prop).into_iter().next()?;
+                match statement.main_snak().data_value() {
+                    Some(dv) => match dv.value() {
+                        wikibase::value::Value::Quantity(q) => Some(QuantityPart::new(q)),
+                        _ => None,
+                    },
+                    None => None,
+                }
+            })
+            .collect();
+        // Metres and feet do not add up
+        let unit = &quantities.first()?.unit;
+        if quantities.iter().any(|quantity| quantity.unit != *unit) {
+            return None;
+        }
+        let values: Vec<f64> = quantities.iter().map(|quantity| quantity.amount).collect();
+        let ret = match function {
+            AggregateFunction::Sum => values.iter().sum(),
+            AggregateFunction::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            AggregateFunction::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            AggregateFunction::Average => values.iter().sum::<f64>() / values.len() as f64,
+        };
+        Some(ret)
+    }
+
     pub fn header_template(&self) -> &Option<String> {
         self.params.header_template()
     }
@@ -1193,32 +1645,99 @@ impl ListeriaList {
         };
         match self.get_entity(entity_id) {
             Some(entity) => {
-                match entity.label_in_locale(use_language).map(|s| s.to_string()) {
-                    Some(s) => s,
-                    None => {
-                        // Try the usual suspects
-                        for language in ["en", "de", "fr", "es", "it", "el", "nl"].iter() {
-                            if let Some(label) =
-                                entity.label_in_locale(language).map(|s| s.to_string())
-                            {
-                                return label;
-                            }
-                        }
-                        // Try any label, any language
-                        if let Some(entity) = self.get_entity(entity_id) {
-                            if let Some(label) = entity.labels().get(0) {
-                                return label.value().to_string();
-                            }
-                        }
-                        // Fallback to item ID as label
-                        entity_id.to_string()
-                    }
+                if let Some(label) = self.get_label_in_fallback_languages(&entity, use_language) {
+                    return label;
                 }
+                // Try any label, any language
+                if let Some(label) = entity.labels().get(0) {
+                    return label.value().to_string();
+                }
+                // Fallback to item ID as label
+                entity_id.to_string()
             }
             None => entity_id.to_string(), // Fallback
         }
     }
 
+    /// Label in the first language of the fallback chain for `language` that has one
+    pub fn get_label_in_fallback_languages(&self, entity: &Entity, language: &str) -> Option<String> {
+        self.language_fallbacks_for(language)
+            .iter()
+            .find_map(|l| entity.label_in_locale(l))
+            .map(|s| s.to_string())
+    }
+
+    /// Description in the first language of the fallback chain for `language` that has one
+    pub fn get_description_in_fallback_languages(&self, entity: &Entity, language: &str) -> Option<String> {
+        self.language_fallbacks_for(language)
+            .iter()
+            .find_map(|l| entity.description_in_locale(l))
+            .map(|s| s.to_string())
+    }
+
+    /// Aliases in the first language of the fallback chain for `language` that has any
+    pub fn get_aliases_in_fallback_languages(&self, entity: &Entity, language: &str) -> Vec<String> {
+        for l in self.language_fallbacks_for(language) {
+            let aliases: Vec<String> = entity
+                .aliases()
+                .iter()
+                .filter(|alias| alias.language() == l)
+                .map(|alias| alias.value().to_string())
+                .collect();
+            if !aliases.is_empty() {
+                return aliases;
+            }
+        }
+        vec![]
+    }
+
+    /// Splits a Wikimedia site ID like "dewiki" or "enwikisource" into its language, if any, and interwiki project prefix
+    fn split_site_id(site: &str) -> Option<(Option<String>, &'static str)> {
+        match site {
+            "commonswiki" => return Some((None, "c")),
+            "wikidatawiki" => return Some((None, "d")),
+            "specieswiki" => return Some((None, "species")),
+            "metawiki" => return Some((None, "m")),
+            "mediawikiwiki" => return Some((None, "mw")),
+            _ => {}
+        }
+        let projects = [
+            ("wikisource", "s"),
+            ("wikiquote", "q"),
+            ("wikibooks", "b"),
+            ("wikinews", "n"),
+            ("wikiversity", "v"),
+            ("wikivoyage", "voy"),
+            ("wiktionary", "wikt"),
+            ("wiki", "w"),
+        ];
+        projects.iter().find_map(|(suffix, project)| {
+            let language = site.strip_suffix(suffix)?;
+            if language.is_empty() {
+                return None;
+            }
+            Some((Some(language.replace('_', "-")), *project))
+        })
+    }
+
+    /// Interwiki prefix to link to a page on another Wikimedia site, like ":de:" or ":s:en:";
+    /// empty for the current wiki, None if the site ID is not recognized
+    pub fn get_interwiki_prefix(&self, site: &str) -> Option<String> {
+        if site == self.wiki() {
+            return Some(String::new());
+        }
+        let (language, project) = Self::split_site_id(site)?;
+        let same_project = match Self::split_site_id(self.wiki()) {
+            Some((Some(_), current_project)) => current_project == project,
+            _ => false,
+        };
+        Some(match language {
+            Some(language) if same_project => format!(":{}:", language),
+            Some(language) => format!(":{}:{}:", project, language),
+            None => format!(":{}:", project),
+        })
+    }
+
     pub fn is_wikidatawiki(&self) -> bool {
         self.page_params.wiki() == "wikidatawiki"
     }
@@ -1275,6 +1794,44 @@ impl ListeriaList {
         }
     }
 
+    /// Languages to try in turn for values in the list language
+    pub fn language_fallbacks(&self) -> Vec<String> {
+        self.language_fallbacks_for(self.language())
+    }
+
+    /// Languages to try in turn for values in `language`, as configured for the wiki
+    pub fn language_fallbacks_for(&self, language: &str) -> Vec<String> {
+        self.page_params
+            .config()
+            .get_language_fallbacks(self.wiki(), language)
+    }
+
+    /// Keeps the monolingual text statements in the first fallback language that has any.
+    /// Statements are returned as they are if no language matches, or if they are not monolingual texts.
+    pub fn filter_monolingual_statements(
+        &self,
+        statements: Vec<wikibase::statement::Statement>,
+    ) -> Vec<wikibase::statement::Statement> {
+        let language_of = |statement: &wikibase::statement::Statement| match statement.main_snak().data_value() {
+            Some(dv) => match dv.value() {
+                wikibase::value::Value::MonoLingual(m) => Some(m.language().to_owned()),
+                _ => None,
+            },
+            None => None,
+        };
+        for language in self.language_fallbacks() {
+            let matching: Vec<wikibase::statement::Statement> = statements
+                .iter()
+                .filter(|statement| language_of(statement).as_deref() == Some(language.as_str()))
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                return matching;
+            }
+        }
+        statements
+    }
+
     pub fn entity_to_local_link(&self, item: &str) -> Option<ResultCellPart> {
         self.ecw
             .entity_to_local_link(item, self.wiki(), &self.language)
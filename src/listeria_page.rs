@@ -6,13 +6,8 @@ use anyhow::{Result,anyhow};
 use crate::{configuration::Configuration, page_element::PageElement, page_params::PageParams, render_wikitext::RendererWikitext, renderer::Renderer, wiki_page_result::WikiPageResult, ApiLock};
 
 /* TODO
-- Sort by P/P, P/Q/P DOES NOT WORK IN LISTERIA-PHP
-
 TESTS:
 - template resolution in SPARQL
-
-TEMPLATE PARAMETERS
-links IMPLEMENT fully?
 */
 
 #[derive(Debug, Clone)]
@@ -97,6 +92,17 @@ impl ListeriaPage {
             promises.push(element.process());
         }
         try_join_all(promises).await.map_err(|e| self.fail(&e.to_string()))?;
+
+        // Subpages are named after the page, so only one list can have them
+        let paginated_lists = self
+            .elements
+            .iter()
+            .filter(|element| !element.is_just_text())
+            .filter(|element| element.list().template_params().per_page().is_some())
+            .count();
+        if paginated_lists > 1 {
+            return Err(self.fail("per_page can only be used by one list on a page"));
+        }
         Ok(())
     }
 
@@ -200,13 +206,20 @@ impl ListeriaPage {
     }
 
     async fn save_wikitext_to_page(&self, title: &str, wikitext: &str) -> Result<()> {
+        if self.page_params.simulate() {
+            println!("SIMULATING: editing [[{}]] on {}", title, self.page_params.wiki());
+            return Ok(());
+        }
+        let summary = self
+            .config()
+            .get_message(self.wiki(), self.language(), "edit_summary");
         let mut api = self.page_params.mw_api().write().await;
         let token = api.get_edit_token().await?;
         let params: HashMap<String, String> = vec![
             ("action", "edit"),
             ("title", title),
             ("text", wikitext),
-            ("summary", "Wikidata list updated [V2]"),
+            ("summary", &summary),
             ("token", &token),
             ("bot","1"),
         ]
@@ -246,9 +259,112 @@ impl ListeriaPage {
             }
         }
 
+        let unused_wikitext = self
+            .config()
+            .get_message(self.wiki(), self.language(), "subpage_unused")
+            .replace("$1", self.page_params.page());
+        for element in &self.elements {
+            let subpages = element.subpages_as_wikitext().map_err(|e| self.fail(&e.to_string()))?;
+            for (title, new_wikitext) in subpages {
+                let old_wikitext = self.load_subpage_wikitext(&title).await.map_err(|e| self.fail(&e.to_string()))?;
+                if let Some(old_wikitext) = &old_wikitext {
+                    if !self.is_bot_subpage(old_wikitext, &unused_wikitext) {
+                        let message = format!("{title} was not written by the bot, and will not be overwritten");
+                        return Err(self.fail(&message));
+                    }
+                }
+                if old_wikitext.as_ref() != Some(&new_wikitext) {
+                    self.save_wikitext_to_page(&title, &new_wikitext)
+                        .await
+                        .map_err(|e| self.fail(&e.to_string()))?;
+                    edited = true;
+                }
+            }
+        }
+
+        // Subpages from an earlier run that needed more pages
+        let number_of_pages = self
+            .elements
+            .iter()
+            .filter(|element| !element.is_just_text())
+            .map(|element| element.list().number_of_pages())
+            .max()
+            .unwrap_or(1);
+        for page in number_of_pages + 1..=self.previous_number_of_pages(&old_wikitext) {
+            let title = format!("{}/{}", self.page_params.page(), page);
+            let old_wikitext = self.load_subpage_wikitext(&title).await.map_err(|e| self.fail(&e.to_string()))?;
+            match old_wikitext {
+                Some(old_wikitext)
+                    if old_wikitext != unused_wikitext && self.is_bot_subpage(&old_wikitext, &unused_wikitext) =>
+                {
+                    self.save_wikitext_to_page(&title, &unused_wikitext)
+                        .await
+                        .map_err(|e| self.fail(&e.to_string()))?;
+                    edited = true;
+                }
+                _ => {} // Gone, already marked as unused, or taken over by someone else
+            }
+        }
+
         Ok(edited)
     }
 
+    /// Number of pages of a list split by per_page, as linked from the page navigation in the old wikitext.
+    /// Subpages are named "Page/2", "Page/3" etc.; the list page itself is page 1.
+    fn previous_number_of_pages(&self, old_wikitext: &str) -> usize {
+        let prefix = format!("[[{}/", self.page_params.page());
+        old_wikitext
+            .match_indices(&prefix)
+            .filter_map(|(pos, _)| {
+                let rest = &old_wikitext[pos + prefix.len()..];
+                let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+                if rest[digits.len()..].starts_with('|') {
+                    digits.parse::<usize>().ok()
+                } else {
+                    None
+                }
+            })
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// A subpage was written by the bot if it links back to page 1 in its page navigation,
+    /// or if it has been marked as unused
+    fn is_bot_subpage(&self, wikitext: &str, unused_wikitext: &str) -> bool {
+        let link_to_first_page = format!("[[{}|1]]", self.page_params.page());
+        wikitext.contains(&link_to_first_page) || wikitext.trim() == unused_wikitext.trim()
+    }
+
+    /// Returns the wikitext of a subpage, or None if it does not exist yet
+    async fn load_subpage_wikitext(&self, title: &str) -> Result<Option<String>> {
+        if self.page_params.simulate() {
+            return Ok(None);
+        }
+        let params: HashMap<String, String> = vec![
+            ("action", "parse"),
+            ("prop", "wikitext"),
+            ("page", title),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let result = self
+            .page_params
+            .mw_api()
+            .read()
+            .await
+            .post_query_api_json(&params)
+            .await?;
+        match result["error"]["code"].as_str() {
+            Some("missingtitle") => Ok(None),
+            Some(other) => Err(anyhow!("{other}")),
+            None => match result["parse"]["wikitext"]["*"].as_str() {
+                Some(wikitext) => Ok(Some(wikitext.to_string())),
+                None => Err(anyhow!("No wikitext for {title}")),
+            },
+        }
+    }
+
     async fn purge_page(&self) -> Result<()> {
         if self.page_params.simulate() {
             println!(
@@ -282,14 +398,15 @@ mod tests {
 
     use crate::listeria_page::ListeriaPage;
     use crate::render_wikitext::RendererWikitext;
+    use crate::wiki_page_result::WikiPageResult;
     use crate::renderer::Renderer;
     use crate::*;
     use std::collections::HashMap;
     use std::fs;
-    use std::io::BufReader;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
     use self::configuration::Configuration;
+    use self::mock_backend::{MockBackend, MockData};
 
     fn read_fixture_from_file(path: PathBuf) -> HashMap<String, String> {
         let text = fs::read_to_string(path).unwrap();
@@ -319,16 +436,35 @@ mod tests {
         data
     }
 
-    async fn check_fixture_file(path: PathBuf) {
-        let data = read_fixture_from_file(path.clone());
-        let mw_api = wikibase::mediawiki::api::Api::new(&data["API"])
+    // Fixtures are served by a local mock backend, without network access
+    async fn offline_api_and_config(data: &HashMap<String, String>) -> (ApiLock, Value, MockBackend) {
+        let (wiki, language) = MockBackend::wiki_and_language_for_api_url(&data["API"]);
+        let mock_data = MockData::new_from_fixture(data, &wiki, &language).unwrap();
+        let backend = MockBackend::start(mock_data).await.unwrap();
+        let mw_api = wikibase::mediawiki::api::Api::new(&backend.api_url(&wiki))
             .await
             .unwrap();
-        let mw_api = Arc::new(RwLock::new(mw_api));
+        let mut j = backend.config_json();
+        // Fixture-specific settings, like translated messages, replace the top-level keys of the mock configuration
+        if let Some(s) = data.get("CONFIG") {
+            let config: Value = serde_json::from_str(s).unwrap();
+            for (k, v) in config.as_object().expect("CONFIG is not a JSON object") {
+                j[k] = v.to_owned();
+            }
+        }
+        (Arc::new(RwLock::new(mw_api)), j, backend)
+    }
 
-        let file = std::fs::File::open("config.json").unwrap();
-        let reader = BufReader::new(file);
-        let mut j: Value = serde_json::from_reader(reader).unwrap();
+    /// Runs the list page of a fixture; SPARQL_RESULTS are used directly if `simulate_sparql` is set,
+    /// otherwise the query goes to the configured SPARQL endpoint
+    async fn run_fixture(
+        path: &Path,
+        data: &HashMap<String, String>,
+        mw_api: ApiLock,
+        j: Value,
+        simulate_sparql: bool,
+    ) -> (ListeriaPage, Result<(), WikiPageResult>) {
+        let mut j = j;
         j["namespace_blocks"] = json!({}); // Allow all namespaces, everywhere
         if path.to_str().unwrap() == "test_data/shadow_images.fixture" {
             // HACKISH
@@ -345,13 +481,22 @@ mod tests {
         let mut page = ListeriaPage::new(config, mw_api, data["PAGETITLE"].clone())
             .await
             .unwrap();
+        let sparql_results = if simulate_sparql {
+            data.get("SPARQL_RESULTS").map(|s| s.to_string())
+        } else {
+            None
+        };
         page.do_simulate(
             data.get("WIKITEXT").map(|s| s.to_string()),
-            data.get("SPARQL_RESULTS").map(|s| s.to_string()),
+            sparql_results,
             data.get("AUTODESC")
                 .map(|s| s.to_string().split('\n').map(|s| s.to_string()).collect()),
         );
-        page.run().await.unwrap();
+        let result = page.run().await;
+        (page, result)
+    }
+
+    fn check_expected(data: &HashMap<String, String>, page: &ListeriaPage) {
         let wt = page.as_wikitext().unwrap();
         let wt = wt.join("\n\n----\n\n");
         let wt = wt.trim().to_string();
@@ -363,6 +508,39 @@ mod tests {
         }
     }
 
+    /// Runs a fixture against the mock backend; fails if the fixture does not bring its own entities
+    async fn check_fixture_file(path: PathBuf) -> ListeriaPage {
+        let data = read_fixture_from_file(path.clone());
+        assert!(
+            data.contains_key("ENTITIES"),
+            "{} has no ENTITIES section",
+            path.display()
+        );
+        let (mw_api, j, backend) = offline_api_and_config(&data).await;
+        let (page, result) = run_fixture(&path, &data, mw_api, j, true).await;
+        result.unwrap();
+        assert_eq!(backend.errors(), Vec::<String>::new());
+        check_expected(&data, &page);
+        page
+    }
+
+    /// Runs an offline fixture with its SPARQL_RESULTS served over HTTP by the mock backend.
+    /// `sparql_config` returns the "sparql" setting for Wikidata in config.json; null uses the endpoint from siteinfo.
+    async fn run_fixture_via_sparql_endpoint(
+        path: PathBuf,
+        sparql_config: fn(&MockBackend) -> Value,
+    ) -> (ListeriaPage, Result<(), WikiPageResult>, MockBackend) {
+        let data = read_fixture_from_file(path.clone());
+        let (mw_api, mut j, backend) = offline_api_and_config(&data).await;
+        let sparql_config = sparql_config(&backend);
+        if !sparql_config.is_null() {
+            j["apis"]["wikidata"] = json!({"url":backend.api_url("wikidata"),"sparql":sparql_config});
+        }
+        let (page, result) = run_fixture(&path, &data, mw_api, j, false).await;
+        assert_eq!(backend.errors(), Vec::<String>::new());
+        (page, result, backend)
+    }
+
     #[tokio::test]
     async fn shadow_images() {
         check_fixture_file(PathBuf::from("test_data/shadow_images.fixture")).await;
@@ -448,6 +626,11 @@ mod tests {
         check_fixture_file(PathBuf::from("test_data/sort_prop_quantity.fixture")).await;
     }
 
+    #[tokio::test]
+    async fn sort_prop_quantity_desc() {
+        check_fixture_file(PathBuf::from("test_data/sort_prop_quantity_desc.fixture")).await;
+    }
+
     #[tokio::test]
     async fn sort_prop_monolingual() {
         check_fixture_file(PathBuf::from("test_data/sort_prop_monolingual.fixture")).await;
@@ -568,14 +751,275 @@ mod tests {
         check_fixture_file(PathBuf::from("test_data/image_pipe.fixture")).await;
     }
 
+    #[tokio::test]
+    async fn offline_basic() {
+        check_fixture_file(PathBuf::from("test_data/offline_basic.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn item_var() {
+        check_fixture_file(PathBuf::from("test_data/item_var.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sparql_typed_literals() {
+        check_fixture_file(PathBuf::from("test_data/sparql_typed_literals.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn section_sparql() {
+        check_fixture_file(PathBuf::from("test_data/section_sparql.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn section_sort_count() {
+        check_fixture_file(PathBuf::from("test_data/section_sort_count.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sections_nested() {
+        check_fixture_file(PathBuf::from("test_data/sections_nested.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sections_nested_level() {
+        check_fixture_file(PathBuf::from("test_data/sections_nested_level.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sort_multiple_keys() {
+        check_fixture_file(PathBuf::from("test_data/sort_multiple_keys.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sort_negative_quantity() {
+        check_fixture_file(PathBuf::from("test_data/sort_negative_quantity.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sort_bce_dates() {
+        check_fixture_file(PathBuf::from("test_data/sort_bce_dates.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sort_label_collation() {
+        check_fixture_file(PathBuf::from("test_data/sort_label_collation.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sort_family_name_properties() {
+        check_fixture_file(PathBuf::from("test_data/sort_family_name_properties.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sort_prop_qual() {
+        check_fixture_file(PathBuf::from("test_data/sort_prop_qual.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sort_prop_qual_value() {
+        check_fixture_file(PathBuf::from("test_data/sort_prop_qual_value.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn filter() {
+        check_fixture_file(PathBuf::from("test_data/filter.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn filter_item_column() {
+        check_fixture_file(PathBuf::from("test_data/filter_item_column.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn limit() {
+        check_fixture_file(PathBuf::from("test_data/limit.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn per_page() {
+        let path = PathBuf::from("test_data/per_page.fixture");
+        let page = check_fixture_file(path.clone()).await;
+        let data = read_fixture_from_file(path);
+        let subpages: Vec<(String, String)> = page
+            .elements()
+            .iter()
+            .flat_map(|element| element.subpages_as_wikitext().unwrap())
+            .map(|(title, wikitext)| (title, wikitext.trim().to_string()))
+            .collect();
+        assert_eq!(subpages, vec![("Irrelevant/2".to_string(), data["EXPECTED_SUBPAGE_2"].to_owned())]);
+
+        let old_wikitext = "{{Wikidata list}}\n----\nPages: 1 · [[Irrelevant/2|2]] · [[Irrelevant/3|3]]\n{{Wikidata list end}}";
+        assert_eq!(page.previous_number_of_pages(old_wikitext), 3);
+        assert_eq!(page.previous_number_of_pages("{{Wikidata list}}{{Wikidata list end}}"), 1);
+
+        let unused_wikitext = "This page is no longer part of the list on [[Irrelevant]].";
+        assert!(page.is_bot_subpage(&data["EXPECTED_SUBPAGE_2"], unused_wikitext));
+        assert!(page.is_bot_subpage(unused_wikitext, unused_wikitext));
+        assert!(!page.is_bot_subpage("Notes on [[Irrelevant]], written by hand", unused_wikitext));
+    }
+
+    #[tokio::test]
+    async fn summary_aggregate() {
+        check_fixture_file(PathBuf::from("test_data/summary_aggregate.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn summary_aggregate_units() {
+        check_fixture_file(PathBuf::from("test_data/summary_aggregate_units.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn messages_dewiki() {
+        check_fixture_file(PathBuf::from("test_data/messages_dewiki.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn column_labels_dewiki() {
+        check_fixture_file(PathBuf::from("test_data/column_labels_dewiki.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn dates() {
+        check_fixture_file(PathBuf::from("test_data/dates.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn date_template() {
+        check_fixture_file(PathBuf::from("test_data/date_template.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn quantities() {
+        check_fixture_file(PathBuf::from("test_data/quantities.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn monolingual_language() {
+        check_fixture_file(PathBuf::from("test_data/monolingual_language.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn links_offline_all() {
+        check_fixture_file(PathBuf::from("test_data/links_offline_all.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn links_offline_local() {
+        check_fixture_file(PathBuf::from("test_data/links_offline_local.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn links_offline_red() {
+        check_fixture_file(PathBuf::from("test_data/links_offline_red.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn links_offline_red_only() {
+        check_fixture_file(PathBuf::from("test_data/links_offline_red_only.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn links_offline_text() {
+        check_fixture_file(PathBuf::from("test_data/links_offline_text.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn links_offline_reasonator() {
+        check_fixture_file(PathBuf::from("test_data/links_offline_reasonator.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn links_offline_viewer() {
+        check_fixture_file(PathBuf::from("test_data/links_offline_viewer.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn language_fallback() {
+        check_fixture_file(PathBuf::from("test_data/language_fallback.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn description_lang_sitelink() {
+        check_fixture_file(PathBuf::from("test_data/description_lang_sitelink.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sitelinks() {
+        check_fixture_file(PathBuf::from("test_data/sitelinks.fixture")).await;
+    }
+
+    #[tokio::test]
+    async fn sparql_fallback_endpoint() {
+        let (page, result, backend) =
+            run_fixture_via_sparql_endpoint(PathBuf::from("test_data/offline_basic.fixture"), |backend| {
+                json!({"endpoint":backend.unavailable_sparql_url(),"fallbacks":[backend.sparql_url()]})
+            })
+            .await;
+        result.unwrap();
+        check_expected(&read_fixture_from_file(PathBuf::from("test_data/offline_basic.fixture")), &page);
+        assert_eq!(backend.request_count("/sparql/unavailable"), 2); // Server errors are retried
+        assert_eq!(backend.request_count("/sparql"), 1);
+    }
+
+    #[tokio::test]
+    async fn sparql_truncated_results_are_retried() {
+        let (page, result, backend) =
+            run_fixture_via_sparql_endpoint(PathBuf::from("test_data/offline_basic.fixture"), |backend| {
+                json!({"endpoint":backend.truncated_sparql_url(),"fallbacks":[backend.sparql_url()]})
+            })
+            .await;
+        result.unwrap();
+        check_expected(&read_fixture_from_file(PathBuf::from("test_data/offline_basic.fixture")), &page);
+        assert_eq!(backend.request_count("/sparql/truncated"), 2);
+        assert_eq!(backend.request_count("/sparql"), 1);
+    }
+
+    #[tokio::test]
+    async fn sparql_auth_header_per_endpoint() {
+        let (_page, result, backend) =
+            run_fixture_via_sparql_endpoint(PathBuf::from("test_data/offline_basic.fixture"), |backend| {
+                json!({
+                    "endpoint":backend.unavailable_sparql_url(),
+                    "auth_header":"Bearer secret",
+                    "fallbacks":[backend.truncated_sparql_url(),{"endpoint":backend.sparql_url(),"auth_header":"Bearer mirror"}]
+                })
+            })
+            .await;
+        result.unwrap();
+        let secret = Some("Bearer secret".to_string());
+        assert_eq!(backend.authorization_headers("/sparql/unavailable"), vec![secret.clone(), secret]);
+        assert_eq!(backend.authorization_headers("/sparql/truncated"), vec![None, None]); // Not sent to fallbacks
+        assert_eq!(backend.authorization_headers("/sparql"), vec![Some("Bearer mirror".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn sparql_xml_response() {
+        let path = PathBuf::from("test_data/sparql_xml.fixture");
+        let (page, result, backend) = run_fixture_via_sparql_endpoint(path.clone(), |_| Value::Null).await;
+        result.unwrap();
+        check_expected(&read_fixture_from_file(path), &page);
+        assert_eq!(backend.request_count("/sparql"), 1);
+    }
+
+    #[tokio::test]
+    async fn sparql_bad_request_is_not_retried() {
+        let (_page, result, backend) =
+            run_fixture_via_sparql_endpoint(PathBuf::from("test_data/offline_basic.fixture"), |backend| {
+                json!({"endpoint":backend.bad_request_sparql_url(),"fallbacks":[backend.sparql_url()]})
+            })
+            .await;
+        let message = result.unwrap_err().message;
+        assert!(message.contains("SPARQL query failed"), "{message}");
+        assert_eq!(backend.request_count("/sparql/bad_request"), 1);
+        assert_eq!(backend.request_count("/sparql"), 0);
+    }
+
     #[tokio::test]
     async fn edit_wikitext() {
         let data = read_fixture_from_file(PathBuf::from("test_data/edit_wikitext.fixture"));
-        let mw_api = wikibase::mediawiki::api::Api::new("https://en.wikipedia.org/w/api.php")
-            .await
-            .unwrap();
-        let mw_api = Arc::new(RwLock::new(mw_api));
-        let config = Arc::new(Configuration::new_from_file("config.json").await.unwrap());
+        let (mw_api, j, backend) = offline_api_and_config(&data).await;
+        let config = Arc::new(Configuration::new_from_json(j).await.unwrap());
         let mut page = ListeriaPage::new(
             config,
             mw_api,
@@ -600,5 +1044,6 @@ mod tests {
             .expect("new_wikitext not Some()");
         let wt = wt.trim().to_string();
         assert_eq!(wt, data["EXPECTED"]);
+        assert_eq!(backend.errors(), Vec::<String>::new());
     }
 }
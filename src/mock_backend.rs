@@ -0,0 +1,489 @@
+/*
+A local stand-in for the MediaWiki, Wikibase and SPARQL web services.
+Fixtures carry their own entity JSON (and optionally siteinfo, expandtemplates output,
+local page existence and results for specific SPARQL queries), which are served from here, so the test
+suite can run without network access.
+
+Every wiki gets its own path prefix, e.g. http://127.0.0.1:12345/enwiki/w/api.php ;
+SPARQL queries go to http://127.0.0.1:12345/sparql . Three more SPARQL endpoints always fail, to test
+error handling: /sparql/unavailable (503), /sparql/bad_request (400) and /sparql/truncated (cut-off JSON).
+*/
+
+use crate::sparql_results::SparqlResults;
+use anyhow::{Result,anyhow};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const TEMPLATE_START_Q: &str = "Q19860885";
+pub const TEMPLATE_END_Q: &str = "Q19860887";
+
+#[derive(Debug, Clone, Default)]
+pub struct MockData {
+    siteinfo: HashMap<String, Value>, // path prefix => siteinfo API response
+    entities: HashMap<String, Value>,
+    expandtemplates: Option<String>,
+    local_pages: Vec<String>,
+    sparql_results: Option<String>,
+    sparql_queries: Vec<(String, String)>, // Part of a query => results for queries containing it
+}
+
+impl MockData {
+    /// Builds the mock data from the sections of a fixture file.
+    /// `wiki` and `language` describe the wiki the fixture page lives on.
+    pub fn new_from_fixture(data: &HashMap<String, String>, wiki: &str, language: &str) -> Result<Self> {
+        let mut ret = Self::default();
+        let siteinfo = match data.get("SITEINFO") {
+            Some(s) => serde_json::from_str(s)?,
+            None => Self::default_siteinfo(wiki, language),
+        };
+        ret.siteinfo.insert(wiki.to_string(), siteinfo);
+        ret.siteinfo.insert("wikidata".to_string(), Self::default_siteinfo("wikidatawiki", "en"));
+
+        if let Some(s) = data.get("ENTITIES") {
+            let j: Value = serde_json::from_str(s)?;
+            let entities = match j["entities"].as_object() {
+                Some(o) => o.to_owned(),
+                None => j.as_object().ok_or_else(|| anyhow!("ENTITIES is not a JSON object"))?.to_owned(),
+            };
+            for (id, entity) in entities {
+                ret.entities.insert(id, entity);
+            }
+        }
+        for q in [TEMPLATE_START_Q, TEMPLATE_END_Q] {
+            if !ret.entities.contains_key(q) {
+                ret.entities.insert(q.to_string(), Self::template_entity(q, wiki));
+            }
+        }
+
+        ret.expandtemplates = data.get("EXPANDTEMPLATES").map(|s| s.to_string());
+        ret.sparql_results = data.get("SPARQL_RESULTS").map(|s| s.to_string());
+        if let Some(s) = data.get("SPARQL_QUERIES") {
+            let j: Value = serde_json::from_str(s)?;
+            for (query_part, results) in j.as_object().ok_or_else(|| anyhow!("SPARQL_QUERIES is not a JSON object"))? {
+                ret.sparql_queries.push((query_part.to_owned(), results.to_string()));
+            }
+        }
+        if let Some(s) = data.get("LOCAL_PAGES") {
+            ret.local_pages = s
+                .split('\n')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        }
+        Ok(ret)
+    }
+
+    fn default_siteinfo(wiki: &str, language: &str) -> Value {
+        let namespaces: Vec<(i64, &str)> = vec![
+            (-2, "Media"),
+            (-1, "Special"),
+            (0, ""),
+            (1, "Talk"),
+            (2, "User"),
+            (3, "User talk"),
+            (4, "Project"),
+            (5, "Project talk"),
+            (6, "File"),
+            (7, "File talk"),
+            (8, "MediaWiki"),
+            (9, "MediaWiki talk"),
+            (10, "Template"),
+            (11, "Template talk"),
+            (12, "Help"),
+            (13, "Help talk"),
+            (14, "Category"),
+            (15, "Category talk"),
+            (120, "Property"),
+            (121, "Property talk"),
+        ];
+        let namespaces: serde_json::Map<String, Value> = namespaces
+            .iter()
+            .map(|(id, name)| {
+                (
+                    id.to_string(),
+                    json!({"id":id,"case":"first-letter","canonical":name,"*":name}),
+                )
+            })
+            .collect();
+        json!({
+            "batchcomplete":"",
+            "query":{
+                "general":{
+                    "wikiid":wiki,
+                    "lang":language,
+                    "sitename":wiki,
+                    "server":"http://127.0.0.1",
+                    "articlepath":"/wiki/$1",
+                    "case":"first-letter"
+                },
+                "namespaces":namespaces,
+                "namespacealiases":[
+                    {"id":6,"*":"Image"},
+                    {"id":4,"*":"WP"}
+                ]
+            }
+        })
+    }
+
+    fn template_entity(q: &str, wiki: &str) -> Value {
+        let title = if q == TEMPLATE_START_Q {
+            "Template:Wikidata list"
+        } else {
+            "Template:Wikidata list end"
+        };
+        json!({
+            "type":"item",
+            "id":q,
+            "labels":{"en":{"language":"en","value":title}},
+            "descriptions":{},
+            "aliases":{},
+            "claims":{},
+            "sitelinks":{
+                wiki:{"site":wiki,"title":title,"badges":[]}
+            }
+        })
+    }
+
+    /// Returns HTTP status, content type and body
+    fn respond(&self, request: &MockRequest) -> (u16, &'static str, String) {
+        match request.path.as_str() {
+            "/sparql" => {
+                // The bot leaves the result format to content negotiation through the Accept header, which works the same
+                // on every endpoint, while format parameters differ between them; this keeps one from creeping back in
+                if request.params.contains_key("format") {
+                    return (400, "text/plain", "Mock backend needs the result format from the Accept header".to_string());
+                }
+                // Results for a specific query, like a region lookup, take precedence over the results for the list
+                let query = request.params.get("query").map(|s| s.as_str()).unwrap_or_default();
+                let results = self
+                    .sparql_queries
+                    .iter()
+                    .find(|(query_part, _results)| query.contains(query_part.as_str()))
+                    .map(|(_query_part, results)| results)
+                    .or(self.sparql_results.as_ref());
+                return match results {
+                    Some(s) => (200, SparqlResults::guess_content_type(s), s.to_string()),
+                    None => (200, "application/json", json!({"head":{"vars":[]},"results":{"bindings":[]}}).to_string()),
+                };
+            }
+            "/sparql/unavailable" => return (503, "text/plain", "Service Unavailable".to_string()),
+            "/sparql/bad_request" => return (400, "text/plain", "MalformedQueryException".to_string()),
+            "/sparql/truncated" => {
+                return (200, "application/json", r#"{"head":{"vars":["item"]},"results":{"bindings":[{"item":"#.to_string())
+            }
+            _ => {}
+        }
+        let site = request
+            .path
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let param = |key: &str| request.params.get(key).map(|s| s.as_str()).unwrap_or_default();
+        let j = match param("action") {
+            "query" if param("meta") == "siteinfo" => match self.siteinfo.get(site) {
+                Some(siteinfo) => siteinfo.to_owned(),
+                None => Self::error("nosuchwiki", site),
+            },
+            "query" => self.query_titles(param("titles")),
+            "wbgetentities" => {
+                let entities: serde_json::Map<String, Value> = param("ids")
+                    .split('|')
+                    .filter(|id| !id.is_empty())
+                    .map(|id| match self.entities.get(id) {
+                        Some(entity) => (id.to_string(), entity.to_owned()),
+                        None => (id.to_string(), json!({"id":id,"missing":""})),
+                    })
+                    .collect();
+                json!({"entities":entities,"success":1})
+            }
+            "expandtemplates" => {
+                let wikitext = match &self.expandtemplates {
+                    Some(s) => s.to_owned(),
+                    None => param("text").to_string(),
+                };
+                json!({"expandtemplates":{"wikitext":wikitext}})
+            }
+            "parse" => {
+                let mode = param("prop");
+                json!({"parse":{"title":param("title"),mode:{"*":param("text")}}})
+            }
+            other => Self::error("badvalue", other),
+        };
+        (200, "application/json", j.to_string())
+    }
+
+    fn query_titles(&self, titles: &str) -> Value {
+        let pages: serde_json::Map<String, Value> = titles
+            .split('|')
+            .filter(|title| !title.is_empty())
+            .enumerate()
+            .map(|(num, title)| {
+                if self.local_pages.iter().any(|page| page == title) {
+                    let pageid = num + 1;
+                    (
+                        pageid.to_string(),
+                        json!({"pageid":pageid,"ns":0,"title":title,"imagerepository":"local"}),
+                    )
+                } else {
+                    (
+                        format!("-{}", num + 1),
+                        json!({"ns":0,"title":title,"missing":"","imagerepository":"shared"}),
+                    )
+                }
+            })
+            .collect();
+        json!({"batchcomplete":"","query":{"pages":pages}})
+    }
+
+    fn error(code: &str, info: &str) -> Value {
+        json!({"error":{"code":code,"info":format!("Not supported by mock backend: {info}")}})
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct MockRequest {
+    path: String,
+    params: HashMap<String, String>,
+    authorization: Option<String>,
+}
+
+impl MockRequest {
+    async fn read_from(stream: &mut TcpStream) -> Result<Self> {
+        let mut buffer: Vec<u8> = vec![];
+        let mut chunk = [0u8; 8192];
+        let header_end = loop {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(anyhow!("Connection closed before end of request headers"));
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+        let header = |name: &str| {
+            head.lines()
+                .filter_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    if key.trim().eq_ignore_ascii_case(name) {
+                        Some(value.trim().to_string())
+                    } else {
+                        None
+                    }
+                })
+                .next()
+        };
+        let content_length = header("content-length")
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(0);
+        let authorization = header("authorization");
+        while buffer.len() < header_end + content_length {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+        let body = String::from_utf8_lossy(&buffer[header_end..]).to_string();
+
+        let target = head
+            .lines()
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .unwrap_or("/")
+            .to_string();
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (target, String::new()),
+        };
+        let mut params = Self::parse_form(&query);
+        params.extend(Self::parse_form(&body));
+        Ok(Self {
+            path,
+            params,
+            authorization,
+        })
+    }
+
+    fn parse_form(s: &str) -> HashMap<String, String> {
+        s.split('&')
+            .filter_map(|pair| {
+                let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                let k = urlencoding::decode(&k.replace('+', " ")).ok()?.to_string();
+                let v = urlencoding::decode(&v.replace('+', " ")).ok()?.to_string();
+                if k.is_empty() {
+                    None
+                } else {
+                    Some((k, v))
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockBackend {
+    port: u16,
+    errors: Arc<Mutex<Vec<String>>>,   // Requests that could not be handled
+    requests: Arc<Mutex<Vec<(String, Option<String>)>>>, // Path and Authorization header of all requests, in order
+}
+
+impl MockBackend {
+    /// Starts serving `data` on a random local port, for as long as the tokio runtime is alive.
+    pub async fn start(data: MockData) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let mut data = data;
+        if let Some(siteinfo) = data.siteinfo.get_mut("wikidata") {
+            siteinfo["query"]["general"]["wikibase-sparql"] =
+                json!(format!("http://127.0.0.1:{port}/sparql"));
+        }
+        let data = Arc::new(data);
+        let errors = Arc::new(Mutex::new(vec![]));
+        let requests = Arc::new(Mutex::new(vec![]));
+        let connection_errors = errors.clone();
+        let connection_requests = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _addr)) = listener.accept().await {
+                let data = data.clone();
+                let errors = connection_errors.clone();
+                let requests = connection_requests.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = Self::handle_connection(stream, data, requests).await {
+                        if let Ok(mut errors) = errors.lock() {
+                            errors.push(e.to_string());
+                        }
+                    }
+                });
+            }
+        });
+        Ok(Self { port, errors, requests })
+    }
+
+    async fn handle_connection(
+        mut stream: TcpStream,
+        data: Arc<MockData>,
+        requests: Arc<Mutex<Vec<(String, Option<String>)>>>,
+    ) -> Result<()> {
+        let request = MockRequest::read_from(&mut stream).await?;
+        if let Ok(mut requests) = requests.lock() {
+            requests.push((request.path.to_owned(), request.authorization.to_owned()));
+        }
+        let (status, content_type, body) = data.respond(&request);
+        let reason = match status {
+            200 => "OK",
+            400 => "Bad Request",
+            _ => "Service Unavailable",
+        };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            reason,
+            content_type,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    /// Errors from connections the backend could not handle so far
+    pub fn errors(&self) -> Vec<String> {
+        match self.errors.lock() {
+            Ok(errors) => errors.clone(),
+            Err(_) => vec!["Mock backend error list is poisoned".to_string()],
+        }
+    }
+
+    /// Number of requests to a path so far
+    pub fn request_count(&self, path: &str) -> usize {
+        match self.requests.lock() {
+            Ok(requests) => requests.iter().filter(|(p, _)| p == path).count(),
+            Err(_) => 0,
+        }
+    }
+
+    /// Authorization headers of the requests to a path so far, in order
+    pub fn authorization_headers(&self, path: &str) -> Vec<Option<String>> {
+        match self.requests.lock() {
+            Ok(requests) => requests
+                .iter()
+                .filter(|(p, _)| p == path)
+                .map(|(_, authorization)| authorization.to_owned())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn api_url(&self, site: &str) -> String {
+        format!("http://127.0.0.1:{}/{}/w/api.php", self.port, site)
+    }
+
+    pub fn sparql_url(&self) -> String {
+        format!("http://127.0.0.1:{}/sparql", self.port)
+    }
+
+    /// A SPARQL endpoint that always answers with 503 Service Unavailable
+    pub fn unavailable_sparql_url(&self) -> String {
+        format!("http://127.0.0.1:{}/sparql/unavailable", self.port)
+    }
+
+    /// A SPARQL endpoint that always answers with 400 Bad Request, like for a syntax error
+    pub fn bad_request_sparql_url(&self) -> String {
+        format!("http://127.0.0.1:{}/sparql/bad_request", self.port)
+    }
+
+    /// A SPARQL endpoint that always answers with JSON results that are cut off, like after a WDQS timeout
+    pub fn truncated_sparql_url(&self) -> String {
+        format!("http://127.0.0.1:{}/sparql/truncated", self.port)
+    }
+
+    /// A configuration equivalent to `config.json.template`, but with all APIs pointing to this backend.
+    pub fn config_json(&self) -> Value {
+        json!({
+            "apis":{
+                "wikidata":self.api_url("wikidata")
+            },
+            "default_language":"en",
+            "default_api":"wikidata",
+            "prefer_preferred":true,
+            "namespace_blocks":{},
+            "location_templates":{
+                "default":"{{Coord|$LAT$|$LON$|display=inline}}",
+                "wikidatawiki":"$LAT$/$LON$",
+                "commonswiki":"{{Inline coordinates|$LAT$|$LON$|display=inline}}",
+                "dewiki":"{{Coordinate|text=DMS|NS=$LAT$|EW=$LON$|name=$ITEM$|simple=y|type=landmark|region=$REGION$}}"
+            },
+            "location_regions":["dewiki"],
+            "default_thumbnail_size":128,
+            "shadow_images_check":["enwiki"],
+            "template_start_q":TEMPLATE_START_Q,
+            "template_end_q":TEMPLATE_END_Q
+        })
+    }
+
+    /// Guesses wiki ID and language from a live API URL, e.g. `https://de.wikipedia.org/w/api.php` => (`dewiki`,`de`).
+    pub fn wiki_and_language_for_api_url(api_url: &str) -> (String, String) {
+        let host = api_url
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        match host {
+            "www.wikidata.org" => ("wikidatawiki".to_string(), "en".to_string()),
+            "commons.wikimedia.org" => ("commonswiki".to_string(), "en".to_string()),
+            "meta.wikimedia.org" => ("metawiki".to_string(), "en".to_string()),
+            other => {
+                let language = other.split('.').next().unwrap_or("en").replace('-', "_");
+                (format!("{language}wiki"), language.replace('_', "-"))
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+/// Formats numbers with the decimal and thousands separators of a language
+#[derive(Debug, Clone)]
+pub struct NumberFormat {
+    decimal_separator: &'static str,
+    thousands_separator: &'static str,
+}
+
+impl NumberFormat {
+    pub fn new(language: &str) -> Self {
+        let language = language.split('-').next().unwrap_or_default().to_lowercase();
+        let (decimal_separator, thousands_separator) = match language.as_str() {
+            "de" | "nl" | "it" | "es" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "sl" | "hr" | "sr" | "ca" | "gl" | "is" => {
+                (",", ".")
+            }
+            "fr" | "sv" | "fi" | "nb" | "nn" | "no" | "cs" | "pl" | "ru" | "uk" | "sk" | "hu" | "bg" | "et" | "lv" | "lt" => {
+                (",", "&nbsp;")
+            }
+            _ => (".", ","),
+        };
+        Self {
+            decimal_separator,
+            thousands_separator,
+        }
+    }
+
+    /// Formats a number with all its significant decimals; thousands are grouped from four digits on
+    pub fn format(&self, n: f64) -> String {
+        let s = format!("{}", n.abs());
+        let (integer, fraction) = match s.split_once('.') {
+            Some((integer, fraction)) => (integer.to_string(), Some(fraction.to_string())),
+            None => (s, None),
+        };
+        let mut ret = String::new();
+        for (pos, c) in integer.chars().enumerate() {
+            if pos > 0 && (integer.len() - pos) % 3 == 0 {
+                ret += self.thousands_separator;
+            }
+            ret.push(c);
+        }
+        if let Some(fraction) = fraction {
+            ret += self.decimal_separator;
+            ret += &fraction;
+        }
+        if n < 0.0 {
+            ret = format!("-{}", ret);
+        }
+        ret
+    }
+
+    /// Formats a number rounded to at most `decimals` decimals
+    pub fn format_rounded(&self, n: f64, decimals: i32) -> String {
+        let factor = 10_f64.powi(decimals);
+        self.format((n * factor).round() / factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_english() {
+        let nf = NumberFormat::new("en");
+        assert_eq!(nf.format(8849.0), "8,849");
+        assert_eq!(nf.format(-1234567.5), "-1,234,567.5");
+        assert_eq!(nf.format(1.96), "1.96");
+    }
+
+    #[test]
+    fn test_german() {
+        let nf = NumberFormat::new("de");
+        assert_eq!(nf.format(1234567.25), "1.234.567,25");
+        assert_eq!(nf.format_rounded(2.0 / 3.0, 2), "0,67");
+    }
+
+    #[test]
+    fn test_french() {
+        let nf = NumberFormat::new("fr");
+        assert_eq!(nf.format(12345.0), "12&nbsp;345");
+    }
+}
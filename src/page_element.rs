@@ -139,6 +139,23 @@ impl PageElement {
         }
     }
 
+    /// Titles and wikitext of the subpages, if the list is split by per_page
+    pub fn subpages_as_wikitext(&self) -> Result<Vec<(String, String)>> {
+        if self.is_just_text {
+            return Ok(vec![]);
+        }
+        (1..self.list.number_of_pages())
+            .map(|page| {
+                let mut renderer = RendererWikitext::new_for_page(page);
+                Ok((self.list.title_for_page(page), renderer.render(&self.list)?))
+            })
+            .collect()
+    }
+
+    pub fn list(&self) -> &ListeriaList {
+        &self.list
+    }
+
     pub fn as_wikitext(&self) -> Result<String> {
         match self.is_just_text {
             true => Ok(self.before.clone()),
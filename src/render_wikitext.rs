@@ -1,29 +1,46 @@
 use anyhow::Result;
-use crate::{listeria_list::ListeriaList, listeria_page::ListeriaPage, renderer::Renderer};
+use crate::{column::ColumnType, listeria_list::ListeriaList, listeria_page::ListeriaPage, renderer::Renderer, result_row::ResultRow, template_params::{AggregateFunction, SummaryType}};
 
-pub struct RendererWikitext {}
+pub struct RendererWikitext {
+    page: usize, // Page of a list split by per_page; 0 is the list page itself
+}
 
 impl Renderer for RendererWikitext {
     fn new() -> Self {
-        Self {}
+        Self { page: 0 }
     }
 
     fn render(&mut self, list: &ListeriaList) -> Result<String> {
-        let mut wt: String = list
-            .get_section_ids()
-            .iter()
-            .map(|section_id| self.as_wikitext_section(list, *section_id))
-            .collect();
+        let mut wt = String::new();
+        let mut previous_section_id: Vec<usize> = vec![];
+        for section_id in list.get_section_ids(self.page) {
+            wt += &self.as_wikitext_section(list, &section_id, &previous_section_id);
+            previous_section_id = section_id;
+        }
 
         if !list.shadow_files().is_empty() {
-            wt += "\n----\nThe following local image(s) are not shown in the above list, because they shadow a Commons image of the same name, and might be non-free:";
+            wt += "\n----\n";
+            wt += &list.message("shadow_files_notice", &[]);
             for file in list.shadow_files() {
                 wt += format!("\n# [[:{}:{}|]]", list.local_file_namespace_prefix(), file).as_str();
             }
         }
 
-        if let Some("ITEMNUMBER") = list.summary().as_deref() {
-            wt += format!("\n----\n&sum; {} items.", list.results().len()).as_str();
+        if list.rows_not_shown() > 0 {
+            let number = list.rows_not_shown().to_string();
+            wt += "\n----\n";
+            wt += &list.message("rows_not_shown", &[&number]);
+        }
+
+        if list.summary().contains(&SummaryType::ItemNumber) {
+            let number = list.results().len().to_string();
+            wt += "\n----\n";
+            wt += &list.message("summary_itemnumber", &[&number]);
+        }
+
+        if list.number_of_pages() > 1 {
+            wt += "\n----\n";
+            wt += &self.as_wikitext_page_links(list);
         }
 
         Ok(wt)
@@ -44,37 +61,66 @@ impl Renderer for RendererWikitext {
 }
 
 impl RendererWikitext {
-    fn as_wikitext_section(&self, list: &ListeriaList, section_id: usize) -> String {
+    /// Renders one page of a list split by per_page
+    pub fn new_for_page(page: usize) -> Self {
+        Self { page }
+    }
+
+    fn as_wikitext_page_links(&self, list: &ListeriaList) -> String {
+        let links: Vec<String> = (0..list.number_of_pages())
+            .map(|page| {
+                if page == self.page {
+                    format!("{}", page + 1)
+                } else {
+                    format!("[[{}|{}]]", list.title_for_page(page), page + 1)
+                }
+            })
+            .collect();
+        list.message("pages", &[&links.join(" · ")])
+    }
+
+    fn as_wikitext_section(&self, list: &ListeriaList, section_id: &[usize], previous_section_id: &[usize]) -> String {
         let mut wt = String::new();
 
-        if let Some(name) = list.section_name(section_id) {
-            let header = format!("\n\n\n== {} ==\n", name);
-            wt += &header;
+        // Headings for this section, and for its parent sections unless the previous section already had them
+        let headers: Vec<String> = (1..=section_id.len())
+            .filter(|depth| previous_section_id.get(..*depth) != section_id.get(..*depth))
+            .filter_map(|depth| {
+                let name = list.section_name(&section_id[..depth])?;
+                // MediaWiki has no headings below level 6
+                let level = (list.template_params().section_level() + depth - 1).min(6);
+                let equals = "=".repeat(level);
+                Some(format!("{} {} {}", equals, name, equals))
+            })
+            .collect();
+        if !headers.is_empty() {
+            wt += &format!("\n\n\n{}\n", headers.join("\n"));
         }
 
         wt += &self.as_wikitext_table_header(list);
 
         if list.get_row_template().is_none()
             && !list.skip_table()
-            && !list.results().is_empty()
+            && !list.row_range_for_page(self.page).is_empty()
             && !list.template_params().wdedit()
         {
             wt += "|-\n";
         }
 
-        let row_entity_ids: Vec<String> = list
-            .results()
+        let section_rows: Vec<&ResultRow> = list
+            .results()[list.row_range_for_page(self.page)]
+            .iter()
+            .filter(|row| row.section().as_slice() == section_id)
+            .collect();
+        let row_entity_ids: Vec<String> = section_rows
             .iter()
-            .filter(|row| row.section() == section_id)
             .map(|row| row.entity_id())
             .cloned()
             .collect();
 
         // Rows
-        let rows = list
-            .results()
+        let rows = section_rows
             .iter()
-            .filter(|row| row.section() == section_id)
             .enumerate()
             .map(|(rownum, row)| row.as_wikitext(list, rownum))
             .collect::<Vec<String>>();
@@ -98,12 +144,76 @@ impl RendererWikitext {
 
         // End
         if !list.skip_table() {
+            wt += &self.as_wikitext_footer_rows(list, &section_rows);
             wt += "\n|}";
         }
 
         wt
     }
 
+    /// Summary rows at the bottom of a table, which stay there when the table is sorted
+    fn as_wikitext_footer_rows(&self, list: &ListeriaList, rows: &[&ResultRow]) -> String {
+        let mut wt = String::new();
+        for summary in list.summary().iter().filter(|summary| summary.is_footer_row()) {
+            if let SummaryType::SectionCount = summary {
+                let number = rows.len().to_string();
+                let text = list.message("summary_section_count", &[&number]);
+                wt += &format!("\n|- class='sortbottom'\n! colspan={} | {}", list.columns().len(), text);
+                continue;
+            }
+            let (label, cells): (String, Vec<String>) = match summary {
+                SummaryType::CellCount => {
+                    let cells = (0..list.columns().len())
+                        .map(|colnum| {
+                            rows.iter()
+                                .filter(|row| match row.cells().get(colnum) {
+                                    Some(cell) => !cell.parts().is_empty(),
+                                    None => false,
+                                })
+                                .count()
+                                .to_string()
+                        })
+                        .collect();
+                    (list.message("summary_cell_count", &[]), cells)
+                }
+                SummaryType::Aggregate((function, prop)) => {
+                    let value = list
+                        .aggregate(function, prop, rows)
+                        .map(|n| list.number_format().format_rounded(n, 2))
+                        .unwrap_or_default();
+                    let cells = list
+                        .columns()
+                        .iter()
+                        .map(|col| match &col.obj {
+                            ColumnType::Property(p) if p == prop => value.to_owned(),
+                            _ => String::new(),
+                        })
+                        .collect();
+                    let key = match function {
+                        AggregateFunction::Sum => "summary_sum",
+                        AggregateFunction::Min => "summary_min",
+                        AggregateFunction::Max => "summary_max",
+                        AggregateFunction::Average => "summary_average",
+                    };
+                    (list.message(key, &[]), cells)
+                }
+                SummaryType::ItemNumber | SummaryType::SectionCount => continue,
+            };
+
+            // The first cell gets the label
+            wt += "\n|- class='sortbottom'";
+            for (colnum, cell) in cells.iter().enumerate() {
+                let cell = match (colnum, cell.is_empty()) {
+                    (0, true) => label.to_owned(),
+                    (0, false) => format!("{}: {}", label, cell),
+                    _ => cell.to_owned(),
+                };
+                wt += &format!("\n! {}", cell);
+            }
+        }
+        wt
+    }
+
     fn as_wikitext_table_header(&self, list: &ListeriaList) -> String {
         let mut wt = String::new();
         match &list.header_template() {
@@ -45,13 +45,13 @@ impl ResultCell {
             }
             ColumnType::Description => {
                 if let Some(e) = entity {
-                    match e.description_in_locale(list.language()) {
+                    match list.get_description_in_fallback_languages(&e, list.language()) {
                         Some(s) => {
                             ret.wdedit_class = match &list.header_template() {
                                 Some(_) => None,
                                 None => Some("wd_desc".to_string())
                             } ;
-                            let s = Self::fix_wikitext_for_output(s);
+                            let s = Self::fix_wikitext_for_output(&s);
                             ret.parts.push(PartWithReference::new(
                                 ResultCellPart::Text(s),
                                 None,
@@ -66,6 +66,67 @@ impl ResultCell {
                     }
                 }
             }
+            ColumnType::DescriptionLang(language) => {
+                if let Some(e) = entity {
+                    let description = if language == "any" {
+                        list.get_description_in_fallback_languages(&e, list.language())
+                            .or_else(|| e.descriptions().get(0).map(|d| d.value().to_string()))
+                    } else {
+                        e.description_in_locale(language).map(|s| s.to_string())
+                    };
+                    if let Some(s) = description {
+                        let s = Self::fix_wikitext_for_output(&s);
+                        ret.parts.push(PartWithReference::new(
+                            ResultCellPart::Text(s),
+                            None,
+                        ));
+                    }
+                }
+            }
+            ColumnType::Sitelink(site) => {
+                if let Some(e) = entity {
+                    let page = match e.sitelinks() {
+                        Some(sl) => sl
+                            .iter()
+                            .filter(|s| *s.site() == *site)
+                            .map(|s| s.title().to_string())
+                            .next(),
+                        None => None,
+                    };
+                    if let Some(page) = page {
+                        ret.parts.push(PartWithReference::new(
+                            ResultCellPart::Sitelink((site.to_owned(), page, false)),
+                            None,
+                        ));
+                    }
+                }
+            }
+            ColumnType::SitelinkCount => {
+                if let Some(e) = entity {
+                    let count = e.sitelinks().iter().flatten().count();
+                    ret.parts.push(PartWithReference::new(
+                        ResultCellPart::Numeric((count.to_string(), count as f64)),
+                        None,
+                    ));
+                }
+            }
+            ColumnType::SitelinkList => {
+                if let Some(e) = entity {
+                    let mut sitelinks: Vec<(String, String)> = e
+                        .sitelinks()
+                        .iter()
+                        .flatten()
+                        .map(|s| (s.site().to_string(), s.title().to_string()))
+                        .collect();
+                    sitelinks.sort();
+                    sitelinks.into_iter().for_each(|(site, page)| {
+                        ret.parts.push(PartWithReference::new(
+                            ResultCellPart::Sitelink((site, page, true)),
+                            None,
+                        ));
+                    });
+                }
+            }
             ColumnType::Field(varname) => {
                 let varname = varname.to_lowercase();
                 let mut found_varname: Option<String> = None;
@@ -93,7 +154,13 @@ impl ResultCell {
                         Some(_) => None,
                         None => Some(format!("wd_{}", property.to_lowercase()))
                     } ;
-                    list.get_filtered_claims(&e, property)
+                    let statements = list.get_filtered_claims(&e, property);
+                    let statements = if list.template_params().monolingual_all() {
+                        statements
+                    } else {
+                        list.filter_monolingual_statements(statements)
+                    };
+                    statements
                         .iter()
                         .for_each(|statement| {
                             let references = match list.get_reference_parameter() {
@@ -103,7 +170,7 @@ impl ResultCell {
                                 _ => None,
                             };
                             ret.parts.push(PartWithReference::new(
-                                ResultCellPart::from_snak(statement.main_snak()),
+                                ResultCellPart::from_snak(statement.main_snak(), list),
                                 references,
                             ));
                         });
@@ -114,7 +181,7 @@ impl ResultCell {
                     list.get_filtered_claims(&e, p1)
                         .iter()
                         .for_each(|statement| {
-                            ret.get_parts_p_p(statement, p2).iter().for_each(|part| {
+                            ret.get_parts_p_p(statement, p2, list).iter().for_each(|part| {
                                 ret.parts
                                     .push(PartWithReference::new(part.to_owned(), None))
                             });
@@ -126,7 +193,7 @@ impl ResultCell {
                     list.get_filtered_claims(&e, p1)
                         .iter()
                         .for_each(|statement| {
-                            ret.get_parts_p_q_p(statement, q1, p2)
+                            ret.get_parts_p_q_p(statement, q1, p2, list)
                                 .iter()
                                 .for_each(|part| {
                                     ret.parts
@@ -137,32 +204,20 @@ impl ResultCell {
             }
             ColumnType::LabelLang(language) => {
                 if let Some(e) = entity {
-                    match e.label_in_locale(language) {
-                        Some(s) => {
-                            ret.parts.push(PartWithReference::new(
-                                ResultCellPart::Text(s.to_string()),
-                                None,
-                            ));
-                        }
-                        None => {
-                            if let Some(s) = e.label_in_locale(list.language()) {
-                                ret.parts.push(PartWithReference::new(
-                                    ResultCellPart::Text(s.to_string()),
-                                    None,
-                                ));
-                            }
-                        }
+                    let label = list
+                        .get_label_in_fallback_languages(&e, language)
+                        .or_else(|| list.get_label_in_fallback_languages(&e, list.language()));
+                    if let Some(s) = label {
+                        ret.parts.push(PartWithReference::new(
+                            ResultCellPart::Text(s),
+                            None,
+                        ));
                     }
                 }
             }
             ColumnType::AliasLang(language) => {
                 if let Some(e) = entity {
-                    let mut aliases: Vec<String> = e
-                        .aliases()
-                        .iter()
-                        .filter(|alias| alias.language() == language)
-                        .map(|alias| alias.value().to_string())
-                        .collect();
+                    let mut aliases = list.get_aliases_in_fallback_languages(&e, language);
                     aliases.sort();
                     aliases.iter().for_each(|alias| {
                         ret.parts.push(PartWithReference::new(
@@ -178,10 +233,9 @@ impl ResultCell {
                         Some(_) => None,
                         None => Some("wd_label".to_string())
                     } ;
-                    let label = match e.label_in_locale(list.language()) {
-                        Some(s) => s.to_string(),
-                        None => entity_id.to_string(),
-                    };
+                    let label = list
+                        .get_label_in_fallback_languages(&e, list.language())
+                        .unwrap_or_else(|| entity_id.to_string());
                     let local_page = match e.sitelinks() {
                         Some(sl) => sl
                             .iter()
@@ -224,6 +278,7 @@ impl ResultCell {
         &self,
         statement: &wikibase::statement::Statement,
         property: &str,
+        list: &ListeriaList,
     ) -> Vec<ResultCellPart> {
         statement
             .qualifiers()
@@ -231,8 +286,8 @@ impl ResultCell {
             .filter(|snak| *snak.property() == *property)
             .map(|snak| {
                 ResultCellPart::SnakList(vec![
-                    PartWithReference::new(ResultCellPart::from_snak(statement.main_snak()), None),
-                    PartWithReference::new(ResultCellPart::from_snak(snak), None),
+                    PartWithReference::new(ResultCellPart::from_snak(statement.main_snak(), list), None),
+                    PartWithReference::new(ResultCellPart::from_snak(snak, list), None),
                 ])
             })
             .collect()
@@ -261,6 +316,7 @@ impl ResultCell {
         statement: &wikibase::statement::Statement,
         target_item: &str,
         property: &str,
+        list: &ListeriaList,
     ) -> Vec<ResultCellPart> {
         let links_to_target = match statement.main_snak().data_value() {
             Some(dv) => match dv.value() {
@@ -282,11 +338,11 @@ impl ResultCell {
                     /*
                     // prefix with the Q in p_q_p
                     PartWithReference::new(
-                        ResultCellPart::from_snak(statement.main_snak()),
+                        ResultCellPart::from_snak(statement.main_snak(), list),
                         None
                     ),
                     */
-                    PartWithReference::new(ResultCellPart::from_snak(snak), None),
+                    PartWithReference::new(ResultCellPart::from_snak(snak, list), None),
                 ])
             })
             .collect()
@@ -297,10 +353,15 @@ impl ResultCell {
             Some(part_with_reference) => match &part_with_reference.part {
                 ResultCellPart::Entity((id, _)) => id.to_owned(),
                 ResultCellPart::LocalLink((page, _label, _)) => page.to_owned(),
-                ResultCellPart::Time(time) => time.to_owned(),
+                ResultCellPart::Sitelink((_site, page, _)) => page.to_owned(),
+                ResultCellPart::Time(time) => time.as_sortable_string(),
+                ResultCellPart::Quantity(quantity) => quantity.amount.to_string(),
                 ResultCellPart::File(s) => s.to_owned(),
                 ResultCellPart::Uri(s) => s.to_owned(),
                 ResultCellPart::Text(s) => s.to_owned(),
+                ResultCellPart::Numeric((lexical, _value)) => lexical.to_owned(),
+                ResultCellPart::Boolean(b) => b.to_string(),
+                ResultCellPart::LangText((text, _language)) => text.to_owned(),
                 ResultCellPart::ExternalId((_prop, id)) => id.to_owned(),
                 _ => String::new(),
             },
@@ -1,6 +1,7 @@
 use crate::column::ColumnType;
 use crate::listeria_list::ListeriaList;
 use crate::reference::Reference;
+use crate::sort_key::JULIAN_CALENDAR;
 use crate::sparql_value::SparqlValue;
 use crate::template_params::LinksType;
 use regex::Regex;
@@ -40,17 +41,172 @@ impl PartWithReference {
     }
 }
 
+/// A date from a Wikibase time value, as stored; Julian calendar dates are not converted
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimePart {
+    pub year: i64, // Negative for BCE
+    pub month: u8, // 0 if unknown
+    pub day: u8,   // 0 if unknown
+    pub precision: u64,
+    pub julian: bool,
+}
+
+impl TimePart {
+    pub fn new(v: &wikibase::TimeValue) -> Option<Self> {
+        lazy_static! {
+            static ref RE_DATE: Regex =
+                Regex::new(r#"^([+-]?\d+)-(\d{1,2})-(\d{1,2})T"#).expect("RE_DATE does not parse");
+        }
+        let caps = RE_DATE.captures(v.time())?;
+        Some(Self {
+            year: caps[1].parse::<i64>().ok()?,
+            month: caps[2].parse::<u8>().ok()?,
+            day: caps[3].parse::<u8>().ok()?,
+            precision: *v.precision(),
+            julian: v.calendarmodel() == JULIAN_CALENDAR,
+        })
+    }
+
+    /// Uses the date template of the wiki if there is one, otherwise the localized date messages.
+    /// In the template, the year has at least four digits, month and day two;
+    /// month and day are empty if the precision does not go down to them.
+    pub fn as_wikitext(&self, list: &ListeriaList) -> String {
+        if let Some(template) = list.get_date_template() {
+            let calendar = if self.julian { "julian" } else { "gregorian" };
+            let year = match self.year < 0 {
+                true => format!("-{:04}", self.year.abs()),
+                false => format!("{:04}", self.year),
+            };
+            let month = match self.precision >= 10 && self.month > 0 {
+                true => format!("{:02}", self.month),
+                false => String::new(),
+            };
+            let day = match self.precision >= 11 && self.month > 0 && self.day > 0 {
+                true => format!("{:02}", self.day),
+                false => String::new(),
+            };
+            return template
+                .replace("$YEAR$", &year)
+                .replace("$MONTH$", &month)
+                .replace("$DAY$", &day)
+                .replace("$PRECISION$", &self.precision.to_string())
+                .replace("$CALENDAR$", calendar);
+        }
+
+        let year = self.year.abs();
+        let ret = match self.precision {
+            6 => list.message("time_millennium", &[&list.ordinal(Self::round_up(year, 1000))]),
+            7 => list.message("time_century", &[&list.ordinal(Self::round_up(year, 100))]),
+            8 => list.message("time_decade", &[&(year / 10 * 10).to_string()]),
+            10 if self.month > 0 => list.message(
+                "date_month",
+                &[&year.to_string(), &list.message(&format!("month_{}", self.month), &[])],
+            ),
+            11 if self.month > 0 && self.day > 0 => list.message(
+                "date_day",
+                &[
+                    &year.to_string(),
+                    &list.message(&format!("month_{}", self.month), &[]),
+                    &self.day.to_string(),
+                ],
+            ),
+            _ => year.to_string(),
+        };
+        let ret = if self.year < 0 {
+            list.message("date_bce", &[&ret])
+        } else {
+            ret
+        };
+
+        // The calendar only matters if there is a month
+        if self.julian && self.precision >= 10 {
+            list.message("date_julian", &[&ret])
+        } else {
+            ret
+        }
+    }
+
+    /// The century or millennium a year is in; 1901-2000 is the 20th century
+    fn round_up(year: i64, span: i64) -> i64 {
+        (year - 1).max(0) / span + 1
+    }
+
+    /// Sortable text, like "+1955-06-08"
+    pub fn as_sortable_string(&self) -> String {
+        format!("{:+05}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// A quantity with its unit item and uncertainty bounds
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantityPart {
+    pub amount: f64,
+    pub unit: Option<String>, // Unit item ID; None for plain numbers
+    pub lower_bound: Option<f64>,
+    pub upper_bound: Option<f64>,
+}
+
+impl QuantityPart {
+    pub fn new(v: &wikibase::QuantityValue) -> Self {
+        // Units are entity URLs, or "1" for plain numbers
+        let unit = v
+            .unit()
+            .rsplit('/')
+            .next()
+            .filter(|id| id.starts_with('Q'))
+            .map(|id| id.to_string());
+        Self {
+            amount: *v.amount(),
+            unit,
+            lower_bound: *v.lower_bound(),
+            upper_bound: *v.upper_bound(),
+        }
+    }
+
+    /// Localized number, with "± x" or "(lower–upper)" if the list shows bounds, and the unit symbol
+    pub fn as_wikitext(&self, list: &ListeriaList) -> String {
+        let number_format = list.number_format();
+        let mut ret = number_format.format(self.amount);
+        if list.template_params().quantity_bounds() {
+            if let (Some(lower), Some(upper)) = (self.lower_bound, self.upper_bound) {
+                // Rounded, so floating point noise does not make symmetric bounds look asymmetric
+                let below = number_format.format_rounded(self.amount - lower, 10);
+                let above = number_format.format_rounded(upper - self.amount, 10);
+                if below == above {
+                    ret += &format!(" ± {}", below);
+                } else {
+                    ret += &format!(
+                        " ({}–{})",
+                        number_format.format(lower),
+                        number_format.format(upper)
+                    );
+                }
+            }
+        }
+        if let Some(unit) = &self.unit {
+            ret += "&nbsp;";
+            ret += &list.get_unit_symbol(unit);
+        }
+        ret
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ResultCellPart {
     Number,
     Entity((String, bool)),            // ID, try_localize
     LocalLink((String, String, bool)), // Page, label, is_category
-    Time(String),
+    Sitelink((String, String, bool)),  // Site ID, page, use site ID as label
+    Time(TimePart),
+    Quantity(QuantityPart),
     Location((f64, f64, Option<String>)),
     File(String),
     Uri(String),
     ExternalId((String, String)), // Property, ID
     Text(String),
+    Numeric((String, f64)), // Lexical form, value
+    Boolean(bool),
+    LangText((String, String)), // Text, language
     SnakList(Vec<PartWithReference>), // PP and PQP
 }
 
@@ -63,6 +219,9 @@ impl ResultCellPart {
             SparqlValue::Time(x) => ResultCellPart::Text(x.to_owned()),
             SparqlValue::Location(x) => ResultCellPart::Location((x.lat, x.lon, None)),
             SparqlValue::Literal(x) => ResultCellPart::Text(x.to_owned()),
+            SparqlValue::Number(x) => ResultCellPart::Numeric(x.to_owned()),
+            SparqlValue::Boolean(x) => ResultCellPart::Boolean(*x),
+            SparqlValue::LangLiteral(x) => ResultCellPart::LangText(x.to_owned()),
         }
     }
 
@@ -82,7 +241,7 @@ impl ResultCellPart {
         }
     }
 
-    pub fn from_snak(snak: &wikibase::Snak) -> Self {
+    pub fn from_snak(snak: &wikibase::Snak, list: &ListeriaList) -> Self {
         match &snak.data_value() {
             Some(dv) => match dv.value() {
                 wikibase::Value::Entity(v) => ResultCellPart::Entity((v.id().to_string(), true)),
@@ -93,51 +252,22 @@ impl ResultCellPart {
                     }
                     _ => ResultCellPart::Text(v.to_string()),
                 },
-                wikibase::Value::Quantity(v) => ResultCellPart::Text(v.amount().to_string()),
-                wikibase::Value::Time(v) => {
-                    match ResultCellPart::reduce_time(&v) {
-                        Some(part) => ResultCellPart::Time(part),
-                        None => ResultCellPart::Text("No/unknown value".to_string()),
-                    }
-                }
+                wikibase::Value::Quantity(v) => ResultCellPart::Quantity(QuantityPart::new(v)),
+                wikibase::Value::Time(v) => match TimePart::new(v) {
+                    Some(time) => ResultCellPart::Time(time),
+                    None => ResultCellPart::Text(v.time().to_string()),
+                },
                 wikibase::Value::Coordinate(v) => {
                     ResultCellPart::Location((*v.latitude(), *v.longitude(), None))
                 }
                 wikibase::Value::MonoLingual(v) => {
-                    ResultCellPart::Text(v.language().to_string() + ":" + v.text())
+                    ResultCellPart::LangText((v.text().to_string(), v.language().to_string()))
                 }
             },
-            _ => ResultCellPart::Text("No/unknown value".to_string()),
+            _ => ResultCellPart::Text(list.message("no_value", &[])),
         }
     }
 
-    pub fn reduce_time(v: &wikibase::TimeValue) -> Option<String> {
-        lazy_static! {
-            static ref RE_DATE: Regex =
-                Regex::new(r#"^\+{0,1}(-{0,1}\d+)-(\d{1,2})-(\d{1,2})T"#).expect("RE_DATE does not parse");
-        }
-        let s = v.time().to_string();
-        let (year, month, day) = match RE_DATE.captures(&s) {
-            Some(caps) => (
-                caps.get(1)?.as_str().to_string(),
-                caps.get(2)?.as_str().to_string(),
-                caps.get(3)?.as_str().to_string(),
-            ),
-            None => {
-                return Some(s);
-            }
-        };
-        Some(match v.precision() {
-            6 => format!("{}th millenium", year[0..year.len() - 2].to_string()),
-            7 => format!("{}th century", year[0..year.len() - 2].to_string()),
-            8 => format!("{}0s", year[0..year.len() - 2].to_string()),
-            9 => year,
-            10 => format!("{}-{}", year, month),
-            11 => format!("{}-{}-{}", year, month, day),
-            _ => s,
-        })
-    }
-
     fn tabbed_string_safe(&self, s: String) -> String {
         let ret = s.replace("\n", " ").replace("\t", " ");
         // 400 chars Max
@@ -159,6 +289,10 @@ impl ResultCellPart {
             ResultCellPart::Number => format!("style='text-align:right'| {}", rownum + 1),
             ResultCellPart::Entity((id, try_localize)) => {
                 if !try_localize {
+                    // Even with links=text, the item column links to the item; it is the only way to get there
+                    if let LinksType::Viewer(viewer) = list.get_links_type() {
+                        return list.entity_viewer_link(viewer, id, id);
+                    }
                     let is_item_column = match list.column(colnum) {
                         Some(col) => col.obj == ColumnType::Item,
                         None => false,
@@ -171,12 +305,8 @@ impl ResultCellPart {
                 }
                 let entity_id_link = list.get_item_link_with_fallback(id);
                 match list.get_entity(id) {
-                    Some(e) => {
-                        let use_language = match e.label_in_locale(list.language()) {
-                            Some(_) => list.language(),
-                            None => list.default_language(),
-                        };
-                        let use_label = list.get_label_with_fallback(id, Some(use_language));
+                    Some(_e) => {
+                        let use_label = list.get_label_with_fallback(id, None);
                         let labeled_entity_link = if list.is_wikidatawiki() {
                             format!("[[{}|{}]]", list.get_item_wiki_target(id), use_label)
                         } else {
@@ -196,16 +326,15 @@ impl ResultCellPart {
                                     format!("[[{}]]", &use_label)
                                 }
                             }
-                            LinksType::Reasonator => {
-                                format!(
-                                    "[https://reasonator.toolforge.org/?q={} {}]",
-                                    id, use_label
-                                )
-                            }
-                            _ => labeled_entity_link,
+                            LinksType::Viewer(viewer) => list.entity_viewer_link(viewer, id, &use_label),
+                            LinksType::All | LinksType::Local => labeled_entity_link,
                         }
                     }
-                    None => entity_id_link,
+                    None => match list.get_links_type() {
+                        LinksType::Text => id.to_owned(),
+                        LinksType::Viewer(viewer) => list.entity_viewer_link(viewer, id, id),
+                        _ => entity_id_link,
+                    },
                 }
             }
             ResultCellPart::LocalLink((title, label, is_category)) => {
@@ -216,7 +345,17 @@ impl ResultCellPart {
                     format!("{}{}|{}]]", &start, &title, &label)
                 }
             }
-            ResultCellPart::Time(time) => time.to_owned(),
+            ResultCellPart::Sitelink((site, page, site_as_label)) => {
+                let label = if *site_as_label { site } else { page };
+                match list.get_interwiki_prefix(site) {
+                    Some(prefix) if prefix.is_empty() && label == page => format!("[[{}]]", page),
+                    Some(prefix) => format!("[[{}{}|{}]]", prefix, page, label),
+                    None if *site_as_label => format!("{}: {}", site, page),
+                    None => page.to_owned(),
+                }
+            }
+            ResultCellPart::Time(time) => time.as_wikitext(list),
+            ResultCellPart::Quantity(quantity) => quantity.as_wikitext(list),
             ResultCellPart::Location((lat, lon, region)) => {
                 let entity_id = match list.results().get(rownum) {
                     Some(row) => Some(row.entity_id().to_string()),
@@ -258,6 +397,15 @@ impl ResultCellPart {
                     None => text.to_owned(),
                 }
             }
+            ResultCellPart::Numeric((lexical, _value)) => lexical.to_owned(),
+            ResultCellPart::Boolean(b) => b.to_string(),
+            ResultCellPart::LangText((text, language)) => {
+                if language == list.language() {
+                    text.to_owned()
+                } else {
+                    format!("<span lang='{}'>{}</span>", language, text)
+                }
+            }
             ResultCellPart::SnakList(v) => v
                 .iter()
                 .map(|rcp| rcp.part.as_wikitext(list, rownum, colnum, partnum))
@@ -1,8 +1,10 @@
-use crate::column::ColumnType;
+use crate::collation::Collator;
 use crate::listeria_list::*;
 use crate::result_cell::ResultCell;
 use crate::result_cell_part::ResultCellPart;
+use crate::sort_key::SortKey;
 use crate::sparql_value::SparqlValue;
+use crate::template_params::SortOrder;
 use regex::Regex;
 use serde_json::Value;
 use std::cmp::Ordering;
@@ -14,8 +16,9 @@ use wikibase::SnakDataType;
 pub struct ResultRow {
     entity_id: String,
     cells: Vec<ResultCell>,
-    section: usize,
-    sortkey: String,
+    sparql_values: HashMap<String, SparqlValue>,
+    section: Vec<usize>, // Section ID for each section level
+    sortkeys: Vec<SortKey>, // One per sort key
     keep: bool,
 }
 
@@ -47,16 +50,25 @@ impl ResultRow {
         &mut self.cells
     }
 
-    pub fn section(&self) -> usize {
-        self.section
+    /// Returns the first value of a SPARQL variable for this row; variable names are case-insensitive
+    pub fn sparql_value(&self, variable: &str) -> Option<&SparqlValue> {
+        let variable = variable.to_lowercase();
+        self.sparql_values
+            .iter()
+            .find(|(k, _v)| k.to_lowercase() == variable)
+            .map(|(_k, v)| v)
+    }
+
+    pub fn section(&self) -> &Vec<usize> {
+        &self.section
     }
 
-    pub fn set_section(&mut self, section: usize) {
+    pub fn set_section(&mut self, section: Vec<usize>) {
         self.section = section;
     }
 
-    pub fn sortkey(&self) -> &String {
-        &self.sortkey
+    pub fn sortkeys(&self) -> &Vec<SortKey> {
+        &self.sortkeys
     }
 
     pub fn set_cells(&mut self, cells: Vec<ResultCell>) {
@@ -96,6 +108,14 @@ impl ResultRow {
         list: &ListeriaList,
         sparql_rows: &[&HashMap<String, SparqlValue>],
     ) {
+        self.sparql_values.clear();
+        for sparql_row in sparql_rows {
+            for (k, v) in sparql_row.iter() {
+                self.sparql_values
+                    .entry(k.to_owned())
+                    .or_insert_with(|| v.to_owned());
+            }
+        }
         self.cells.clear();
         for column in list.columns().iter() {
             let x = ResultCell::new(list, &self.entity_id, sparql_rows, column).await;
@@ -103,44 +123,139 @@ impl ResultRow {
         }
     }
 
-    pub fn set_sortkey(&mut self, sortkey: String) {
-        self.sortkey = sortkey;
+    pub fn set_sortkeys(&mut self, sortkeys: Vec<SortKey>) {
+        self.sortkeys = sortkeys;
+    }
+
+    pub fn get_sortkey_label(&self, list: &ListeriaList) -> SortKey {
+        match list.get_entity(self.entity_id()) {
+            Some(_entity) => SortKey::Text(list.get_label_with_fallback(self.entity_id(), None)),
+            None => SortKey::Text(String::new()),
+        }
     }
 
-    pub fn get_sortkey_label(&self, list: &ListeriaList) -> String {
+    /// Number of sitelinks of the item, to sort by how many projects cover it
+    pub fn get_sortkey_sitelink_count(&self, list: &ListeriaList) -> SortKey {
         match list.get_entity(self.entity_id()) {
-            Some(_entity) => list.get_label_with_fallback(self.entity_id(), None),
-            None => "".to_string(),
+            Some(entity) => SortKey::Number(entity.sitelinks().iter().flatten().count() as f64),
+            None => SortKey::None,
+        }
+    }
+
+    /// Page title of the item on another site
+    pub fn get_sortkey_sitelink(&self, site: &str, list: &ListeriaList) -> SortKey {
+        let entity = match list.get_entity(self.entity_id()) {
+            Some(entity) => entity,
+            None => return SortKey::None,
+        };
+        match entity.sitelinks() {
+            Some(sl) => sl
+                .iter()
+                .find(|s| *s.site() == site)
+                .map(|s| SortKey::Text(s.title().to_string()))
+                .unwrap_or(SortKey::None),
+            None => SortKey::None,
+        }
+    }
+
+    /// Uses family name (P734) and given name (P735) items if present, otherwise guesses from the name
+    pub fn get_sortkey_family_name(&self, list: &ListeriaList) -> SortKey {
+        let entity = match list.get_entity(&self.entity_id) {
+            Some(entity) => entity,
+            None => return SortKey::Text(String::new()),
+        };
+        let name = list
+            .get_label_in_fallback_languages(&entity, list.language())
+            .or_else(|| Self::get_monolingual_name(&entity, list));
+
+        let family_names = Self::get_item_labels_prop(&entity, "P734", list);
+        if !family_names.is_empty() {
+            let family_name = family_names.join(" ");
+            let given_names = Self::get_item_labels_prop(&entity, "P735", list);
+            let given_name = match given_names.is_empty() {
+                true => name
+                    .map(|name| name.replace(&family_name, "").trim().to_string())
+                    .unwrap_or_default(),
+                false => given_names.join(" "),
+            };
+            return match given_name.is_empty() {
+                true => SortKey::Text(family_name),
+                false => SortKey::Text(format!("{}, {}", family_name, given_name)),
+            };
         }
+
+        match name {
+            Some(name) => SortKey::Text(Self::family_name_from_name(&name, list.language())),
+            None => SortKey::Text(entity.id().to_string()),
+        }
+    }
+
+    /// Name in native language (P1559) or birth name (P1477)
+    fn get_monolingual_name(entity: &wikibase::Entity, list: &ListeriaList) -> Option<String> {
+        ["P1559", "P1477"].iter().find_map(|prop| {
+            list.get_filtered_claims(entity, prop)
+                .iter()
+                .filter_map(|statement| match statement.main_snak().data_value() {
+                    Some(data_value) => match data_value.value() {
+                        wikibase::value::Value::MonoLingual(m) => Some(m.text().to_owned()),
+                        _ => None,
+                    },
+                    None => None,
+                })
+                .next()
+        })
+    }
+
+    fn get_item_labels_prop(entity: &wikibase::Entity, prop: &str, list: &ListeriaList) -> Vec<String> {
+        list.get_filtered_claims(entity, prop)
+            .iter()
+            .filter_map(|statement| match statement.main_snak().data_value() {
+                Some(data_value) => match data_value.value() {
+                    wikibase::value::Value::Entity(item) => {
+                        Some(list.get_label_with_fallback(&item.id(), None))
+                    }
+                    _ => None,
+                },
+                None => None,
+            })
+            .collect()
     }
 
-    pub fn get_sortkey_family_name(&self, page: &ListeriaList) -> String {
+    /// Guesses "family name, given names" from a full name
+    fn family_name_from_name(name: &str, language: &str) -> String {
         lazy_static! {
             static ref RE_SR_JR: Regex = Regex::new(r", [JS]r\.$").expect("RE_SR_JR does not parse");
             static ref RE_BRACES: Regex = Regex::new(r"\s+\(.+\)$").expect("RE_BRACES does not parse");
-            static ref RE_LAST_FIRST: Regex = Regex::new(r"^(?P<f>.+) (?P<l>\S+)$").expect("RE_LAST_FIRST does not parse");
         }
-        match page.get_entity(&self.entity_id) {
-            Some(entity) => match entity.label_in_locale(&page.language()) {
-                Some(label) => {
-                    let ret = RE_SR_JR.replace_all(label, "");
-                    let ret = RE_BRACES.replace_all(&ret, "");
-                    let ret = RE_LAST_FIRST.replace_all(&ret, "$l, $f");
-                    ret.to_string()
-                }
-                None => entity.id().to_string(),
-            },
-            None => "".to_string(),
+        // Sorted after the given names, eg "Gogh, Vincent van"
+        const PARTICLES: &[&str] = &[
+            "van", "von", "der", "den", "de", "del", "della", "di", "da", "du", "le", "la", "ter", "ten", "zu",
+        ];
+        let name = RE_SR_JR.replace_all(name, "");
+        let name = RE_BRACES.replace_all(&name, "");
+        let words: Vec<&str> = name.split_whitespace().collect();
+
+        // Mononyms, and languages that put the family name first
+        if words.len() < 2 || matches!(language, "zh" | "ja" | "ko" | "hu") {
+            return words.join(" ");
         }
+
+        // Spanish-style double surnames, eg "García Márquez, Gabriel"
+        let is_particle = |word: &&str| PARTICLES.contains(&word.to_lowercase().as_str());
+        let family_name_words = match language {
+            "es" | "ca" | "gl" if words.len() >= 3 && !words[words.len() - 2..].iter().any(is_particle) => 2,
+            _ => 1,
+        };
+        let (given_names, family_names) = words.split_at(words.len() - family_name_words);
+        format!("{}, {}", family_names.join(" "), given_names.join(" "))
     }
 
-    fn no_value(&self, datatype: &SnakDataType) -> String {
+    /// Rows without a value go last for times, monolingual texts and quantities, and first otherwise
+    fn no_value(&self, datatype: &SnakDataType) -> SortKey {
         match *datatype {
-            SnakDataType::Time => "no time",
-            SnakDataType::MonolingualText => "zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz",
-            _ => "",
+            SnakDataType::Time | SnakDataType::MonolingualText | SnakDataType::Quantity => SortKey::None,
+            _ => SortKey::Text(String::new()),
         }
-        .to_string()
     }
 
     pub fn get_sortkey_prop(
@@ -148,11 +263,12 @@ impl ResultRow {
         prop: &str,
         list: &ListeriaList,
         datatype: &SnakDataType,
-    ) -> String {
+    ) -> SortKey {
         match list.get_entity(&self.entity_id) {
             Some(entity) => {
-                match list
-                    .get_filtered_claims(&entity, prop) 
+                // Monolingual texts sort by the value in the page language
+                let statements = list.filter_monolingual_statements(list.get_filtered_claims(&entity, prop));
+                match statements
                     .iter()
                     .filter(|statement| statement.property() == prop)
                     .map(|statement| statement.main_snak())
@@ -166,80 +282,145 @@ impl ResultRow {
         }
     }
 
-    pub fn get_sortkey_sparql(&self, variable: &str, list: &ListeriaList) -> String {
-        let obj = ColumnType::Field(variable.to_lowercase());
-        // TODO sort by actual sparql values instead?
-        match list
-            .columns()
+    /// Sorts by the first `qualifier` of the first statement for `prop`; with `target_item`, only statements with that value count
+    pub fn get_sortkey_prop_qual(
+        &self,
+        prop: &str,
+        target_item: Option<&str>,
+        qualifier: &str,
+        list: &ListeriaList,
+        datatype: &SnakDataType,
+    ) -> SortKey {
+        let entity = match list.get_entity(&self.entity_id) {
+            Some(entity) => entity,
+            None => return self.no_value(datatype),
+        };
+        let snak = list
+            .get_filtered_claims(&entity, prop)
             .iter()
-            .enumerate()
-            .find(|(_colnum, col)| col.obj == obj)
+            .filter(|statement| statement.property() == prop)
+            .filter(|statement| match target_item {
+                Some(target_item) => match statement.main_snak().data_value() {
+                    Some(dv) => match dv.value() {
+                        wikibase::value::Value::Entity(e) => e.id() == target_item,
+                        _ => false,
+                    },
+                    None => false,
+                },
+                None => true,
+            })
+            .filter_map(|statement| {
+                statement
+                    .qualifiers()
+                    .iter()
+                    .find(|snak| *snak.property() == *qualifier)
+                    .cloned()
+            })
+            .next();
+        match snak {
+            Some(snak) => self.get_sortkey_from_snak(&snak, list),
+            None => self.no_value(datatype),
+        }
+    }
+
+    /// Returns the item ID of the first value for the property, if that is an item
+    pub fn get_item_id_prop(&self, prop: &str, list: &ListeriaList) -> Option<String> {
+        let entity = list.get_entity(&self.entity_id)?;
+        let snak = list
+            .get_filtered_claims(&entity, prop)
+            .iter()
+            .filter(|statement| statement.property() == prop)
+            .map(|statement| statement.main_snak().to_owned())
+            .next()?;
+        match snak.data_value()?.value() {
+            wikibase::value::Value::Entity(entity) => Some(entity.id().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the section this row belongs to for a property, or an empty string
+    pub fn get_section_name_prop(&self, prop: &str, list: &ListeriaList) -> String {
+        let entity = match list.get_entity(&self.entity_id) {
+            Some(entity) => entity,
+            None => return String::new(),
+        };
+        let snak = match list
+            .get_filtered_claims(&entity, prop)
+            .iter()
+            .filter(|statement| statement.property() == prop)
+            .map(|statement| statement.main_snak().to_owned())
+            .next()
         {
-            Some((colnum, _col)) => match self.cells.get(colnum) {
-                Some(cell) => cell.get_sortkey(),
-                None => String::new(),
+            Some(snak) => snak,
+            None => return String::new(),
+        };
+        match snak.data_value() {
+            Some(data_value) => match data_value.value() {
+                wikibase::value::Value::Coordinate(c) => format!("{}/{}", c.latitude(), c.longitude()),
+                wikibase::value::Value::MonoLingual(m) => m.text().to_owned(),
+                wikibase::value::Value::Entity(entity) => list.get_label_with_fallback(&entity.id(), None),
+                wikibase::value::Value::Quantity(q) => format!("{}", q.amount()),
+                wikibase::value::Value::StringValue(s) => s.to_owned(),
+                wikibase::value::Value::Time(t) => t.time().to_owned(),
             },
             None => String::new(),
         }
     }
 
-    fn get_sortkey_from_snak(&self, snak: &wikibase::snak::Snak, list: &ListeriaList) -> String {
+    pub fn get_sortkey_sparql(&self, variable: &str, list: &ListeriaList) -> SortKey {
+        match self.sparql_value(variable) {
+            Some(SparqlValue::Entity(id)) => SortKey::Text(list.get_label_with_fallback(id, None)),
+            Some(SparqlValue::Number((_lexical, value))) => SortKey::Number(*value),
+            Some(SparqlValue::Boolean(b)) => SortKey::Number(if *b { 1.0 } else { 0.0 }),
+            Some(SparqlValue::Time(time)) => SortKey::new_from_time(time, "", 11),
+            Some(SparqlValue::Location(loc)) => SortKey::Coordinate((loc.lat, loc.lon)),
+            Some(SparqlValue::LangLiteral((text, _language))) => SortKey::Text(text.to_owned()),
+            Some(SparqlValue::File(s)) | Some(SparqlValue::Uri(s)) | Some(SparqlValue::Literal(s)) => {
+                SortKey::Text(s.to_owned())
+            }
+            None => SortKey::None,
+        }
+    }
+
+    fn get_sortkey_from_snak(&self, snak: &wikibase::snak::Snak, list: &ListeriaList) -> SortKey {
         match snak.data_value() {
             Some(data_value) => match data_value.value() {
-                wikibase::value::Value::Coordinate(c) => format!(
-                    "{}/{}/{}",
-                    c.latitude(),
-                    c.longitude(),
-                    c.precision().unwrap_or(0.0)
-                ),
-                wikibase::value::Value::MonoLingual(m) => format!("{}:{}", m.language(), m.text()),
+                wikibase::value::Value::Coordinate(c) => SortKey::Coordinate((*c.latitude(), *c.longitude())),
+                wikibase::value::Value::MonoLingual(m) => SortKey::Text(m.text().to_owned()),
                 wikibase::value::Value::Entity(entity) => {
                     // TODO language?
-                    list.get_label_with_fallback(&entity.id(), None)
+                    SortKey::Text(list.get_label_with_fallback(&entity.id(), None))
+                }
+                wikibase::value::Value::Quantity(q) => SortKey::Number(*q.amount()),
+                wikibase::value::Value::StringValue(s) => SortKey::Text(s.to_owned()),
+                wikibase::value::Value::Time(t) => {
+                    SortKey::new_from_time(t.time(), t.calendarmodel(), *t.precision())
                 }
-                wikibase::value::Value::Quantity(q) => format!("{}", q.amount()),
-                wikibase::value::Value::StringValue(s) => s.to_owned(),
-                wikibase::value::Value::Time(t) => t.time().to_owned(),
             },
-            None => "".to_string(),
+            None => SortKey::None,
         }
     }
 
-    fn compare_entiry_ids(&self, other: &ResultRow) -> Ordering {
-        let id1 = self.entity_id[1..]
-            .parse::<usize>()
-            .ok()
-            .or(Some(0))
-            .unwrap_or(0);
-        let id2 = other.entity_id[1..]
-            .parse::<usize>()
-            .ok()
-            .or(Some(0))
-            .unwrap_or(0);
-        id1.partial_cmp(&id2).unwrap_or(Ordering::Equal)
-    }
-
-    pub fn compare_to(&self, other: &ResultRow, datatype: &SnakDataType) -> Ordering {
-        match datatype {
-            SnakDataType::Quantity => {
-                let va = self.sortkey.parse::<u64>().ok().or(Some(0)).unwrap_or(0);
-                let vb = other.sortkey.parse::<u64>().ok().or(Some(0)).unwrap_or(0);
-                if va == 0 && vb == 0 {
-                    self.compare_entiry_ids(other)
-                } else {
-                    va.partial_cmp(&vb).unwrap_or(Ordering::Equal)
-                }
-            }
-            _ => {
-                if self.sortkey == other.sortkey {
-                    self.compare_entiry_ids(other)
-                } else {
-                    self.sortkey
-                        .partial_cmp(&other.sortkey)
-                        .unwrap_or(Ordering::Equal)
-                }
+    /// Compares by each sort key in turn, with its own direction
+    pub fn compare_to(&self, other: &ResultRow, orders: &[SortOrder], collator: &Collator) -> Ordering {
+        for (num, order) in orders.iter().enumerate() {
+            let (a, b) = match (self.sortkeys.get(num), other.sortkeys.get(num)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => break,
+            };
+            // Rows without a value go last in either direction
+            let ordering = match (a, b, order) {
+                (SortKey::None, SortKey::None, _) => Ordering::Equal,
+                (SortKey::None, _, _) => Ordering::Greater,
+                (_, SortKey::None, _) => Ordering::Less,
+                (_, _, SortOrder::Ascending) => a.compare(b, collator),
+                (_, _, SortOrder::Descending) => b.compare(a, collator),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
             }
         }
+        Ordering::Equal
     }
 
     pub fn as_tabbed_data(&self, list: &ListeriaList, rownum: usize) -> Value {
@@ -249,7 +430,7 @@ impl ResultRow {
             .enumerate()
             .map(|(colnum, cell)| cell.as_tabbed_data(list, rownum, colnum))
             .collect();
-        ret.insert(0, json!(self.section));
+        ret.insert(0, json!(self.section.first().unwrap_or(&0)));
         json!(ret)
     }
 
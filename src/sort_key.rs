@@ -0,0 +1,115 @@
+use crate::collation::Collator;
+use regex::Regex;
+use std::cmp::Ordering;
+
+pub const JULIAN_CALENDAR: &str = "http://www.wikidata.org/entity/Q1985786";
+
+/// A typed value to sort rows by
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum SortKey {
+    Number(f64),
+    Time((i64, u8, u8)), // Proleptic Gregorian year, month, day; month/day are 0 if unknown
+    Coordinate((f64, f64)),
+    Text(String),
+    #[default]
+    None, // No value, sorts after everything else
+}
+
+impl SortKey {
+    /// Parses a Wikibase time string like "+1955-06-08T00:00:00Z" or "-0500-00-00T00:00:00Z".
+    /// Julian calendar dates are converted, so they compare correctly with Gregorian ones.
+    pub fn new_from_time(time: &str, calendarmodel: &str, precision: u64) -> Self {
+        lazy_static! {
+            static ref RE_TIME: Regex = Regex::new(r"^([+-]?\d+)-(\d{1,2})-(\d{1,2})").expect("RE_TIME does not parse");
+        }
+        let caps = match RE_TIME.captures(time) {
+            Some(caps) => caps,
+            None => return Self::Text(time.to_string()),
+        };
+        let year = caps[1].parse::<i64>().unwrap_or(0);
+        let month = caps[2].parse::<u8>().unwrap_or(0);
+        let day = caps[3].parse::<u8>().unwrap_or(0);
+        // Precision 11 is day
+        if calendarmodel == JULIAN_CALENDAR && precision >= 11 && month > 0 && day > 0 {
+            return Self::Time(Self::julian_to_gregorian(year, month, day));
+        }
+        Self::Time((year, month, day))
+    }
+
+    /// Returns true if there is nothing to sort by
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::None => true,
+            Self::Text(s) => s.is_empty(),
+            _ => false,
+        }
+    }
+
+    pub fn compare(&self, other: &Self, collator: &Collator) -> Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Self::Time(a), Self::Time(b)) => a.cmp(b),
+            (Self::Coordinate((lat_a, lon_a)), Self::Coordinate((lat_b, lon_b))) => lat_a
+                .partial_cmp(lat_b)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| lon_a.partial_cmp(lon_b).unwrap_or(Ordering::Equal)),
+            (Self::Text(a), Self::Text(b)) => collator.compare(a, b),
+            (a, b) => a.type_rank().cmp(&b.type_rank()), // Mixed types, or no value
+        }
+    }
+
+    fn type_rank(&self) -> u8 {
+        match self {
+            Self::Number(_) => 0,
+            Self::Time(_) => 1,
+            Self::Coordinate(_) => 2,
+            Self::Text(_) => 3,
+            Self::None => 4,
+        }
+    }
+
+    /// Converts a Julian calendar date to the proleptic Gregorian calendar, via the Julian day number
+    fn julian_to_gregorian(year: i64, month: u8, day: u8) -> (i64, u8, u8) {
+        let (month, day) = (month as i64, day as i64);
+        let a = (14 - month) / 12;
+        let y = year + 4800 - a;
+        let m = month + 12 * a - 3;
+        let jdn = day + (153 * m + 2) / 5 + 365 * y + y.div_euclid(4) - 32083;
+
+        let a = jdn + 32044;
+        let b = (4 * a + 3).div_euclid(146097);
+        let c = a - (146097 * b).div_euclid(4);
+        let d = (4 * c + 3).div_euclid(1461);
+        let e = c - (1461 * d).div_euclid(4);
+        let m = (5 * e + 2) / 153;
+        let day = e - (153 * m + 2) / 5 + 1;
+        let month = m + 3 - 12 * (m / 10);
+        let year = 100 * b + d - 4800 + m / 10;
+        (year, month as u8, day as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bce_before_ce() {
+        let bce = SortKey::new_from_time("-0500-00-00T00:00:00Z", "", 9);
+        let ce = SortKey::new_from_time("+0050-00-00T00:00:00Z", "", 9);
+        assert_eq!(bce.compare(&ce, &Collator::new("en")), Ordering::Less);
+    }
+
+    #[test]
+    fn test_julian_calendar() {
+        let julian = SortKey::new_from_time("+1582-10-05T00:00:00Z", JULIAN_CALENDAR, 11);
+        assert_eq!(julian, SortKey::Time((1582, 10, 15)));
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        let collator = Collator::new("en");
+        assert_eq!(SortKey::Number(-5.5).compare(&SortKey::Number(2.0), &collator), Ordering::Less);
+        assert_eq!(SortKey::Number(2.0).compare(&SortKey::None, &collator), Ordering::Less);
+    }
+}
@@ -0,0 +1,371 @@
+use crate::sparql_value::SparqlValue;
+use anyhow::{Result,anyhow};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(Debug, Clone, Default)]
+pub struct SparqlResults {
+    vars: Vec<String>,
+    rows: Vec<HashMap<String, SparqlValue>>,
+}
+
+impl SparqlResults {
+    /// Parses a SPARQL response body, using the format given by the HTTP content type.
+    /// Falls back to guessing the format from the text itself.
+    pub fn new_from_response(text: &str, content_type: &str) -> Result<Self> {
+        let content_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        let content_type = if content_type.is_empty() || content_type == "text/plain" {
+            Self::guess_content_type(text).to_string()
+        } else {
+            content_type
+        };
+        match content_type.as_str() {
+            "application/sparql-results+xml" | "application/xml" | "text/xml" => {
+                Self::new_from_xml(text)
+            }
+            "text/tab-separated-values" => Self::new_from_tsv(text),
+            "text/csv" => Self::new_from_csv(text),
+            _ => {
+                let j: Value = serde_json::from_str(text)
+                    .map_err(|_| anyhow!("SPARQL is probably broken"))?;
+                Self::new_from_json(&j)
+            }
+        }
+    }
+
+    /// Parses a SPARQL response body, guessing the format from the text
+    pub fn new_from_text(text: &str) -> Result<Self> {
+        Self::new_from_response(text, Self::guess_content_type(text))
+    }
+
+    pub fn guess_content_type(text: &str) -> &'static str {
+        let text = text.trim_start();
+        match text.chars().next() {
+            Some('<') => "application/sparql-results+xml",
+            Some('?') => "text/tab-separated-values",
+            Some('{') => "application/sparql-results+json",
+            _ => match text.lines().next() {
+                Some(first_line) if first_line.contains('\t') => "text/tab-separated-values",
+                Some(first_line) if first_line.contains(',') => "text/csv",
+                _ => "application/sparql-results+json",
+            },
+        }
+    }
+
+    pub fn new_from_json(j: &Value) -> Result<Self> {
+        let vars = j["head"]["vars"]
+            .as_array()
+            .ok_or(anyhow!("Bad SPARQL head.vars"))?
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|v| v.to_string())
+            .collect();
+        let bindings = j["results"]["bindings"]
+            .as_array()
+            .ok_or(anyhow!("Broken SPARQL results.bindings"))?;
+        let mut rows = vec![];
+        for b in bindings.iter() {
+            let mut row: HashMap<String, SparqlValue> = HashMap::new();
+            if let Some(bo) = b.as_object() {
+                for (k, v) in bo.iter() {
+                    match SparqlValue::new_from_json(&v) {
+                        Some(v2) => row.insert(k.to_owned(), v2),
+                        None => {
+                            return Err(anyhow!("Can't parse SPARQL value: {} => {:?}", &k, &v))
+                        }
+                    };
+                }
+            }
+            if !row.is_empty() {
+                rows.push(row);
+            }
+        }
+        Ok(Self { vars, rows })
+    }
+
+    /// Parses the SPARQL Query Results XML Format
+    pub fn new_from_xml(text: &str) -> Result<Self> {
+        let doc = roxmltree::Document::parse(text)?;
+        let vars = doc
+            .descendants()
+            .filter(|node| node.has_tag_name("variable"))
+            .filter_map(|node| node.attribute("name"))
+            .map(|name| name.to_string())
+            .collect();
+        let mut rows = vec![];
+        for result in doc.descendants().filter(|node| node.has_tag_name("result")) {
+            let mut row: HashMap<String, SparqlValue> = HashMap::new();
+            for binding in result.children().filter(|node| node.has_tag_name("binding")) {
+                let name = binding
+                    .attribute("name")
+                    .ok_or_else(|| anyhow!("SPARQL XML binding without name"))?;
+                let term = match binding.children().find(|node| node.is_element()) {
+                    Some(term) => term,
+                    None => continue,
+                };
+                let value = term.text().unwrap_or_default();
+                let j = match term.tag_name().name() {
+                    "uri" => json!({"type":"uri","value":value}),
+                    "bnode" => json!({"type":"bnode","value":value}),
+                    "literal" => {
+                        let mut j = json!({"type":"literal","value":value});
+                        if let Some(datatype) = term.attribute("datatype") {
+                            j["datatype"] = json!(datatype);
+                        }
+                        if let Some(language) = term.attribute((XML_NAMESPACE, "lang")) {
+                            j["xml:lang"] = json!(language);
+                        }
+                        j
+                    }
+                    other => return Err(anyhow!("Unknown SPARQL XML term '{other}' for {name}")),
+                };
+                match SparqlValue::new_from_json(&j) {
+                    Some(v) => row.insert(name.to_string(), v),
+                    None => return Err(anyhow!("Can't parse SPARQL value: {} => {:?}", name, &j)),
+                };
+            }
+            if !row.is_empty() {
+                rows.push(row);
+            }
+        }
+        Ok(Self { vars, rows })
+    }
+
+    /// Parses the SPARQL 1.1 TSV results format, where each value is an RDF term in Turtle syntax
+    pub fn new_from_tsv(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        let vars: Vec<String> = lines
+            .next()
+            .ok_or_else(|| anyhow!("Empty SPARQL TSV results"))?
+            .split('\t')
+            .map(|var| var.trim().trim_start_matches(|c| c == '?' || c == '$').to_string())
+            .collect();
+        let mut rows = vec![];
+        for line in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut row: HashMap<String, SparqlValue> = HashMap::new();
+            for (var, term) in vars.iter().zip(line.split('\t')) {
+                if term.is_empty() {
+                    continue; // Unbound
+                }
+                let j = Self::tsv_term_as_json(term);
+                match SparqlValue::new_from_json(&j) {
+                    Some(v) => row.insert(var.to_owned(), v),
+                    None => return Err(anyhow!("Can't parse SPARQL value: {} => {:?}", var, &j)),
+                };
+            }
+            if !row.is_empty() {
+                rows.push(row);
+            }
+        }
+        Ok(Self { vars, rows })
+    }
+
+    fn tsv_term_as_json(term: &str) -> Value {
+        lazy_static! {
+            static ref RE_INTEGER: Regex = Regex::new(r"^[+-]?\d+$").expect("RE_INTEGER does not parse");
+            static ref RE_DECIMAL: Regex = Regex::new(r"^[+-]?\d*\.\d+$").expect("RE_DECIMAL does not parse");
+            static ref RE_DOUBLE: Regex =
+                Regex::new(r"^[+-]?(\d+\.?\d*|\.\d+)[eE][+-]?\d+$").expect("RE_DOUBLE does not parse");
+        }
+        if term.starts_with('<') && term.ends_with('>') {
+            return json!({"type":"uri","value":&term[1..term.len() - 1]});
+        }
+        if let Some(bnode) = term.strip_prefix("_:") {
+            return json!({"type":"bnode","value":bnode});
+        }
+        if term.starts_with('"') {
+            if let Some(end) = term.rfind('"').filter(|end| *end > 0) {
+                let value = Self::unescape_turtle_string(&term[1..end]);
+                let suffix = &term[end + 1..];
+                let mut j = json!({"type":"literal","value":value});
+                if let Some(language) = suffix.strip_prefix('@') {
+                    j["xml:lang"] = json!(language);
+                } else if let Some(datatype) = suffix.strip_prefix("^^<").and_then(|s| s.strip_suffix('>')) {
+                    j["datatype"] = json!(datatype);
+                }
+                return j;
+            }
+        }
+        let datatype = match term {
+            "true" | "false" => Some("boolean"),
+            t if RE_INTEGER.is_match(t) => Some("integer"),
+            t if RE_DECIMAL.is_match(t) => Some("decimal"),
+            t if RE_DOUBLE.is_match(t) => Some("double"),
+            _ => None,
+        };
+        match datatype {
+            Some(datatype) => json!({"type":"literal","value":term,"datatype":format!("{XSD}{datatype}")}),
+            None => json!({"type":"literal","value":term}),
+        }
+    }
+
+    fn unescape_turtle_string(s: &str) -> String {
+        let mut ret = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                ret.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('t') => ret.push('\t'),
+                Some('n') => ret.push('\n'),
+                Some('r') => ret.push('\r'),
+                Some(other) => ret.push(other),
+                None => ret.push('\\'),
+            }
+        }
+        ret
+    }
+
+    /// Parses the SPARQL 1.1 CSV results format. CSV carries no types, so anything that
+    /// looks like a URL becomes a URI, and everything else a plain literal.
+    pub fn new_from_csv(text: &str) -> Result<Self> {
+        let mut records = Self::split_csv_records(text).into_iter();
+        let vars: Vec<String> = records
+            .next()
+            .ok_or_else(|| anyhow!("Empty SPARQL CSV results"))?
+            .iter()
+            .map(|var| var.trim().to_string())
+            .collect();
+        let mut rows = vec![];
+        for record in records {
+            let mut row: HashMap<String, SparqlValue> = HashMap::new();
+            for (var, value) in vars.iter().zip(record.iter()) {
+                if value.is_empty() {
+                    continue; // Unbound
+                }
+                let j = if value.starts_with("http://") || value.starts_with("https://") {
+                    json!({"type":"uri","value":value})
+                } else if let Some(bnode) = value.strip_prefix("_:") {
+                    json!({"type":"bnode","value":bnode})
+                } else {
+                    json!({"type":"literal","value":value})
+                };
+                match SparqlValue::new_from_json(&j) {
+                    Some(v) => row.insert(var.to_owned(), v),
+                    None => return Err(anyhow!("Can't parse SPARQL value: {} => {:?}", var, &j)),
+                };
+            }
+            if !row.is_empty() {
+                rows.push(row);
+            }
+        }
+        Ok(Self { vars, rows })
+    }
+
+    // RFC 4180 records; quoted fields may contain commas, doubled quotes, and line breaks
+    fn split_csv_records(text: &str) -> Vec<Vec<String>> {
+        let mut records = vec![];
+        let mut record = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                }
+                ('"', false) => quoted = true,
+                (',', false) => record.push(std::mem::take(&mut field)),
+                ('\r', false) => {}
+                ('\n', false) => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                (c, _) => field.push(c),
+            }
+        }
+        if !field.is_empty() || !record.is_empty() {
+            record.push(field);
+            records.push(record);
+        }
+        records.retain(|record| record.iter().any(|field| !field.is_empty()));
+        records
+    }
+
+    pub fn vars(&self) -> &Vec<String> {
+        &self.vars
+    }
+
+    pub fn rows(&self) -> &Vec<HashMap<String, SparqlValue>> {
+        &self.rows
+    }
+
+    pub fn into_rows(self) -> Vec<HashMap<String, SparqlValue>> {
+        self.rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml() {
+        let xml = r#"<?xml version="1.0"?>
+<sparql xmlns="http://www.w3.org/2005/sparql-results#">
+  <head><variable name="item"/><variable name="label"/></head>
+  <results>
+    <result>
+      <binding name="item"><uri>http://www.wikidata.org/entity/Q42</uri></binding>
+      <binding name="label"><literal>Douglas Adams</literal></binding>
+    </result>
+    <result>
+      <binding name="item"><uri>http://www.wikidata.org/entity/Q1</uri></binding>
+    </result>
+  </results>
+</sparql>"#;
+        let results = SparqlResults::new_from_response(xml, "application/sparql-results+xml").unwrap();
+        assert_eq!(*results.vars(), vec!["item".to_string(), "label".to_string()]);
+        assert_eq!(results.rows().len(), 2);
+        assert_eq!(results.rows()[0]["item"], SparqlValue::Entity("Q42".to_string()));
+        assert_eq!(results.rows()[0]["label"], SparqlValue::Literal("Douglas Adams".to_string()));
+        assert!(results.rows()[1].get("label").is_none());
+    }
+
+    #[test]
+    fn test_tsv() {
+        let tsv = "?item\t?label\t?file\n<http://www.wikidata.org/entity/Q42>\t\"Douglas \\\"DNA\\\" Adams\"\t<http://commons.wikimedia.org/wiki/Special:FilePath/Douglas%20adams.jpg>\n<http://www.wikidata.org/entity/Q1>\t\t\n";
+        let results = SparqlResults::new_from_response(tsv, "text/tab-separated-values; charset=utf-8").unwrap();
+        assert_eq!(results.rows().len(), 2);
+        assert_eq!(results.rows()[0]["label"], SparqlValue::Literal("Douglas \"DNA\" Adams".to_string()));
+        assert_eq!(results.rows()[0]["file"], SparqlValue::File("Douglas adams.jpg".to_string()));
+        assert_eq!(results.rows()[1].len(), 1);
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = "item,label\r\nhttp://www.wikidata.org/entity/Q42,\"Adams, Douglas\"\r\n";
+        let results = SparqlResults::new_from_response(csv, "text/csv").unwrap();
+        assert_eq!(results.rows().len(), 1);
+        assert_eq!(results.rows()[0]["item"], SparqlValue::Entity("Q42".to_string()));
+        assert_eq!(results.rows()[0]["label"], SparqlValue::Literal("Adams, Douglas".to_string()));
+    }
+
+    #[test]
+    fn test_typed_literals() {
+        let tsv = "?height\t?alive\t?label\n\"1.96\"^^<http://www.w3.org/2001/XMLSchema#decimal>\t\"false\"^^<http://www.w3.org/2001/XMLSchema#boolean>\t\"Douglas Adams\"@en\n";
+        let results = SparqlResults::new_from_response(tsv, "text/tab-separated-values").unwrap();
+        assert_eq!(results.rows()[0]["height"], SparqlValue::Number(("1.96".to_string(), 1.96)));
+        assert_eq!(results.rows()[0]["alive"], SparqlValue::Boolean(false));
+        assert_eq!(results.rows()[0]["label"], SparqlValue::LangLiteral(("Douglas Adams".to_string(), "en".to_string())));
+    }
+}
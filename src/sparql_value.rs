@@ -21,6 +21,9 @@ pub enum SparqlValue {
     Time(String),
     Location(LatLon),
     Literal(String),
+    Number((String, f64)), // Lexical form, value
+    Boolean(bool),
+    LangLiteral((String, String)), // Text, language
 }
 
 impl SparqlValue {
@@ -80,7 +83,24 @@ impl SparqlValue {
                     };
                     Some(SparqlValue::Time(time))
                 }
-                _ => Some(SparqlValue::Literal(value.to_string())),
+                Some(datatype) if Self::is_numeric_datatype(datatype) => {
+                    let lexical = value.trim();
+                    match lexical.parse::<f64>() {
+                        Ok(number) => Some(SparqlValue::Number((lexical.to_string(), number))),
+                        Err(_) => Some(SparqlValue::Literal(value.to_string())),
+                    }
+                }
+                Some("http://www.w3.org/2001/XMLSchema#boolean") => {
+                    Some(SparqlValue::Boolean(value == "true" || value == "1"))
+                }
+                Some(_) => Some(SparqlValue::Literal(value.to_string())),
+                None => match j["xml:lang"].as_str() {
+                    Some(language) if !language.is_empty() => Some(SparqlValue::LangLiteral((
+                        value.to_string(),
+                        language.to_lowercase(),
+                    ))),
+                    _ => Some(SparqlValue::Literal(value.to_string())),
+                },
             },
             Some("bnode") => match j["value"].as_str() {
                 Some(value) => Some(SparqlValue::Literal(value.to_string())),
@@ -89,4 +109,29 @@ impl SparqlValue {
             _ => None,
         }
     }
+
+    fn is_numeric_datatype(datatype: &str) -> bool {
+        match datatype.strip_prefix("http://www.w3.org/2001/XMLSchema#") {
+            Some(xsd_type) => matches!(
+                xsd_type,
+                "integer"
+                    | "decimal"
+                    | "double"
+                    | "float"
+                    | "int"
+                    | "long"
+                    | "short"
+                    | "byte"
+                    | "nonNegativeInteger"
+                    | "nonPositiveInteger"
+                    | "positiveInteger"
+                    | "negativeInteger"
+                    | "unsignedLong"
+                    | "unsignedInt"
+                    | "unsignedShort"
+                    | "unsignedByte"
+            ),
+            None => false,
+        }
+    }
 }
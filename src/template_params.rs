@@ -2,6 +2,7 @@
 
 use regex::Regex;
 
+use crate::column::ColumnType;
 use crate::template::Template;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -11,17 +12,21 @@ pub enum LinksType {
     Red,
     RedOnly,
     Text,
-    Reasonator,
+    Viewer(String), // Named entity viewer from the configuration, eg "scholia"
 }
 
 impl LinksType {
     pub fn new_from_string(s: String) -> Self {
-        match s.trim().to_uppercase().as_str() {
-            "LOCAL" => Self::Local,
-            "RED" => Self::Red,
-            "RED_ONLY" => Self::RedOnly,
-            "TEXT" => Self::Text,
-            "REASONATOR" => Self::Reasonator,
+        let s = s.trim().to_lowercase();
+        if let Some(name) = s.strip_prefix("viewer:") {
+            return Self::Viewer(name.trim().to_string());
+        }
+        match s.as_str() {
+            "local" => Self::Local,
+            "red" => Self::Red,
+            "red_only" => Self::RedOnly,
+            "text" => Self::Text,
+            "reasonator" => Self::Viewer("reasonator".to_string()), // Before there were viewers
             _ => Self::All, // Fallback, default
         }
     }
@@ -32,7 +37,11 @@ pub enum SortMode {
     Label,
     FamilyName,
     Property(String),
+    PropertyQualifier((String, String)),              // Property, qualifier
+    PropertyQualifierValue((String, String, String)), // Property, target item, qualifier
     SparqlVariable(String),
+    SitelinkCount,
+    Sitelink(String), // Site ID; sorts by page title
     None,
 }
 
@@ -40,7 +49,7 @@ impl SortMode {
     pub fn new(os: Option<&String>) -> Self {
         lazy_static! {
             static ref RE_PROP: Regex = Regex::new(r"^P\d+$").expect("RE_PROP does not parse");
-            static ref RE_SPARQL: Regex = Regex::new(r"^?\S+$").expect("RE_SPARQL does not parse");
+            static ref RE_SPARQL: Regex = Regex::new(r"^\?\S+$").expect("RE_SPARQL does not parse");
         }
         let os = os.map(|s| s.trim().to_uppercase());
         match os {
@@ -53,7 +62,14 @@ impl SortMode {
                     } else if RE_SPARQL.is_match(other) {
                         Self::SparqlVariable(other[1..].to_string())
                     } else {
-                        Self::None
+                        // Same P/P and P/Q/P expressions as columns
+                        match ColumnType::new(other) {
+                            ColumnType::PropertyQualifier(pq) => Self::PropertyQualifier(pq),
+                            ColumnType::PropertyQualifierValue(pqp) => Self::PropertyQualifierValue(pqp),
+                            ColumnType::SitelinkCount => Self::SitelinkCount,
+                            ColumnType::Sitelink(site) => Self::Sitelink(site),
+                            _ => Self::None,
+                        }
                     }
                 }
             },
@@ -72,7 +88,7 @@ impl SortOrder {
     pub fn new(os: Option<&String>) -> Self {
         match os {
             Some(s) => {
-                if s.to_uppercase().trim() == "DESC" {
+                if matches!(s.to_uppercase().trim(), "DESC" | "DESCENDING") {
                     Self::Descending
                 } else {
                     Self::Ascending
@@ -83,6 +99,144 @@ impl SortOrder {
     }
 }
 
+/// One key of a sort specification like "P569 desc, label asc"
+#[derive(Debug, Clone)]
+pub struct SortCriterion {
+    pub mode: SortMode,
+    pub order: SortOrder,
+}
+
+impl SortCriterion {
+    /// Keys without an explicit direction use `default_order`
+    pub fn new_list(os: Option<&String>, default_order: &SortOrder) -> Vec<Self> {
+        let s = match os {
+            Some(s) => s,
+            None => return vec![],
+        };
+        s.split(',')
+            .filter_map(|part| {
+                let mut tokens = part.split_whitespace();
+                let mode = SortMode::new(Some(&tokens.next()?.to_string()));
+                if let SortMode::None = mode {
+                    return None;
+                }
+                let order = match tokens.next() {
+                    Some(order) => SortOrder::new(Some(&order.to_string())),
+                    None => default_order.to_owned(),
+                };
+                Some(Self { mode, order })
+            })
+            .collect()
+    }
+}
+
+/// A rule from the filter parameter; rows that fail any rule are removed
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowFilter {
+    HasProperty(String),
+    PropertyValue((String, String)), // Property, item
+    Sitelink,                        // On this wiki
+    Label,                           // In the page language
+    Description,                     // In the page language
+    Not(Box<RowFilter>),
+}
+
+impl RowFilter {
+    /// Parses rules like "has P18; P31=Q5; no sitelink; no label"
+    pub fn new_list(os: Option<&String>) -> Vec<Self> {
+        match os {
+            Some(s) => s.split(';').filter_map(Self::new).collect(),
+            None => vec![],
+        }
+    }
+
+    fn new(s: &str) -> Option<Self> {
+        lazy_static! {
+            static ref RE_HAS: Regex = Regex::new(r"^(HAS|NO)\s+(\S+)$").expect("RE_HAS does not parse");
+            static ref RE_PROP: Regex = Regex::new(r"^P\d+$").expect("RE_PROP does not parse");
+            static ref RE_PROP_VALUE: Regex =
+                Regex::new(r"^(P\d+)\s*(!?=)\s*(Q\d+)$").expect("RE_PROP_VALUE does not parse");
+        }
+        let s = s.trim().to_uppercase();
+        if RE_PROP.is_match(&s) {
+            return Some(Self::HasProperty(s));
+        }
+        if let Some(caps) = RE_PROP_VALUE.captures(&s) {
+            let filter = Self::PropertyValue((caps[1].to_string(), caps[3].to_string()));
+            return match &caps[2] {
+                "!=" => Some(Self::Not(Box::new(filter))),
+                _ => Some(filter),
+            };
+        }
+        let caps = RE_HAS.captures(&s)?;
+        let filter = match &caps[2] {
+            "SITELINK" => Self::Sitelink,
+            "LABEL" => Self::Label,
+            "DESCRIPTION" => Self::Description,
+            prop if RE_PROP.is_match(prop) => Self::HasProperty(prop.to_string()),
+            _ => return None,
+        };
+        match &caps[1] {
+            "NO" => Some(Self::Not(Box::new(filter))),
+            _ => Some(filter),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AggregateFunction {
+    Sum,
+    Min,
+    Max,
+    Average,
+}
+
+/// One entry of the summary parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum SummaryType {
+    ItemNumber,                             // Total number of rows, below the list
+    SectionCount,                           // Number of rows, as a footer row of each table
+    CellCount,                              // Number of non-empty cells per column, as a footer row
+    Aggregate((AggregateFunction, String)), // Over the quantities of a property column, as a footer row
+}
+
+impl SummaryType {
+    /// Parses lists like "itemnumber, section_count, cell_count, sum:P1082, average:P2044"
+    pub fn new_list(os: Option<&String>) -> Vec<Self> {
+        match os {
+            Some(s) => s.split(',').filter_map(Self::new).collect(),
+            None => vec![],
+        }
+    }
+
+    fn new(s: &str) -> Option<Self> {
+        lazy_static! {
+            static ref RE_AGGREGATE: Regex =
+                Regex::new(r"^(SUM|MIN|MAX|AVERAGE)\s*:\s*(P\d+)$").expect("RE_AGGREGATE does not parse");
+        }
+        let s = s.trim().to_uppercase();
+        match s.as_str() {
+            "ITEMNUMBER" => return Some(Self::ItemNumber),
+            "SECTION_COUNT" => return Some(Self::SectionCount),
+            "CELL_COUNT" => return Some(Self::CellCount),
+            _ => {}
+        }
+        let caps = RE_AGGREGATE.captures(&s)?;
+        let function = match &caps[1] {
+            "SUM" => AggregateFunction::Sum,
+            "MIN" => AggregateFunction::Min,
+            "MAX" => AggregateFunction::Max,
+            _ => AggregateFunction::Average,
+        };
+        Some(Self::Aggregate((function, caps[2].to_string())))
+    }
+
+    /// Returns true for summaries rendered as a footer row of each table
+    pub fn is_footer_row(&self) -> bool {
+        !matches!(self, Self::ItemNumber)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ReferencesParameter {
     None,
@@ -109,19 +263,31 @@ pub enum SectionType {
     None,
     Property(String),
     SparqlVariable(String),
+    Nested(Vec<SectionType>), // Outermost first, eg "P131/P31"
 }
 
 impl SectionType {
     pub fn new_from_string_option(s: Option<&String>) -> Self {
+        let s = match s {
+            Some(s) => s.trim(),
+            None => return Self::None,
+        };
+        if !s.contains('/') {
+            return Self::new_single_level(s);
+        }
+        let levels: Vec<Self> = s.split('/').map(Self::new_single_level).collect();
+        if levels.iter().any(|level| matches!(level, Self::None)) {
+            return Self::None;
+        }
+        Self::Nested(levels)
+    }
+
+    fn new_single_level(s: &str) -> Self {
         lazy_static! {
             static ref RE_PROP : Regex = Regex::new(r"^[Pp]\d+$").expect("RE_PROP does not parse");
             static ref RE_PROP_NUM : Regex = Regex::new(r"^\d+$").expect("RE_PROP_NUM does not parse"); // Yes people do that!
             static ref RE_SPARQL : Regex = Regex::new(r"^@.+$").expect("RE_SPARQL does not parse");
         }
-        let s = match s {
-            Some(s) => s,
-            None => return Self::None,
-        };
         let s = s.trim();
         if RE_PROP.is_match(s) {
             return Self::Property(s.to_uppercase());
@@ -130,29 +296,78 @@ impl SectionType {
             return Self::Property(format!("P{}", &s));
         }
         if RE_SPARQL.is_match(s) {
-            return Self::SparqlVariable(s.to_uppercase());
+            return Self::SparqlVariable(s[1..].to_uppercase());
         }
         Self::None
     }
+
+    /// Returns the section levels, outermost first; empty if there are no sections
+    pub fn levels(&self) -> Vec<Self> {
+        match self {
+            Self::None => vec![],
+            Self::Nested(levels) => levels.to_owned(),
+            other => vec![other.to_owned()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectionSort {
+    Alphabetical,
+    Count,
+    FirstAppearance,
+    Property(String),
 }
 
+impl SectionSort {
+    pub fn new(os: Option<&String>) -> Self {
+        lazy_static! {
+            static ref RE_PROP: Regex = Regex::new(r"^P\d+$").expect("RE_PROP does not parse");
+        }
+        let os = os.map(|s| s.trim().to_uppercase());
+        match os {
+            Some(s) => match s.as_str() {
+                "COUNT" => Self::Count,
+                "FIRST" | "APPEARANCE" => Self::FirstAppearance,
+                other => {
+                    if RE_PROP.is_match(other) {
+                        Self::Property(other.to_string())
+                    } else {
+                        Self::Alphabetical
+                    }
+                }
+            },
+            None => Self::Alphabetical,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TemplateParams {
     links: LinksType,
-    sort: SortMode,
+    sort: Vec<SortCriterion>,
     section: SectionType,
     min_section: u64,
+    section_sort: SectionSort,
+    section_level: usize,
+    section_misc: Option<String>,
     row_template: Option<String>,
     header_template: Option<String>,
     autodesc: Option<String>,
-    summary: Option<String>,
+    summary: Vec<SummaryType>,
     skip_table: bool,
     wdedit: bool,
     references: ReferencesParameter,
     one_row_per_item: bool,
     sort_order: SortOrder,
     wikibase: String,
+    item_var: String,
+    filters: Vec<RowFilter>,
+    limit: Option<usize>,
+    per_page: Option<usize>,
+    quantity_bounds: bool,
+    monolingual_all: bool,
+    language: Option<String>,
 }
 
 impl Default for TemplateParams {
@@ -165,32 +380,59 @@ impl TemplateParams {
     pub fn new() -> Self {
         Self {
             links: LinksType::All,
-            sort: SortMode::None,
+            sort: vec![],
             section: SectionType::None,
             min_section: 2,
+            section_sort: SectionSort::Alphabetical,
+            section_level: 2,
+            section_misc: None,
             row_template: None,
             header_template: None,
             autodesc: None,
-            summary: None,
+            summary: vec![],
             skip_table: false,
             wdedit: false,
             references: ReferencesParameter::None,
             one_row_per_item: false,
             sort_order: SortOrder::Ascending,
             wikibase: String::new(),
+            item_var: "item".to_string(),
+            filters: vec![],
+            limit: None,
+            per_page: None,
+            quantity_bounds: false,
+            monolingual_all: false,
+            language: None,
         }
     }
 
     pub fn new_from_params(template: &Template) -> Self {
         Self {
-            links: LinksType::All,
-            sort: SortMode::new(template.params.get("sort")),
+            links: Self::get_template_value(template, "links")
+                .map(LinksType::new_from_string)
+                .unwrap_or(LinksType::All),
+            sort: SortCriterion::new_list(
+                template.params.get("sort"),
+                &SortOrder::new(template.params.get("sort_order")),
+            ),
             section: SectionType::new_from_string_option(template.params.get("section")),
             min_section: template
                 .params
                 .get("min_section")
                 .map(|s| s.parse::<u64>().ok().or(Some(2)).unwrap_or(2))
                 .unwrap_or(2),
+            section_sort: SectionSort::new(template.params.get("section_sort")),
+            section_level: template
+                .params
+                .get("section_level")
+                .and_then(|s| s.trim().parse::<usize>().ok())
+                .map(|level| level.clamp(1, 6))
+                .unwrap_or(2),
+            section_misc: template
+                .params
+                .get("section_misc")
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
             row_template: template
                 .params
                 .get("row_template")
@@ -209,10 +451,7 @@ impl TemplateParams {
                         .get("autodesc")
                         .map(|s| s.trim().to_uppercase())
                 }),
-            summary: template
-                .params
-                .get("summary")
-                .map(|s| s.trim().to_uppercase()),
+            summary: SummaryType::new_list(template.params.get("summary")),
             skip_table: template.params.get("skip_table").is_some(),
             one_row_per_item: template
                 .params
@@ -231,9 +470,79 @@ impl TemplateParams {
                 .get("wikibase")
                 .map(|s| s.trim().to_uppercase())
                 .unwrap_or_else(|| "wikidata".to_string()), // TODO config
+            item_var: template
+                .params
+                .get("item_var")
+                .map(|s| s.trim().trim_start_matches(|c| c == '?' || c == '$').to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| "item".to_string()),
+            filters: RowFilter::new_list(template.params.get("filter")),
+            limit: Self::positive_number(template.params.get("limit")),
+            per_page: Self::positive_number(template.params.get("per_page")),
+            quantity_bounds: template
+                .params
+                .get("quantity_bounds")
+                .map(|s| s.trim().to_uppercase())
+                == Some("YES".to_string()),
+            monolingual_all: template
+                .params
+                .get("monolingual")
+                .map(|s| s.trim().to_uppercase())
+                == Some("ALL".to_string()),
+            language: Self::get_template_value(template, "language")
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty()),
         }
     }
 
+    /// Like `template.params.get(key)`, but ignores the case of the key
+    fn get_template_value(template: &Template, key: &str) -> Option<String> {
+        template
+            .params
+            .iter()
+            .filter(|(k, _v)| k.to_lowercase() == key.to_lowercase())
+            .map(|(_k, v)| v.to_owned())
+            .next()
+    }
+
+    fn positive_number(os: Option<&String>) -> Option<usize> {
+        os.and_then(|s| s.trim().parse::<usize>().ok())
+            .filter(|n| *n > 0)
+    }
+
+    /// Maximum number of rows to show; the rest are counted in a note
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Show monolingual texts in all languages, not just the page language
+    pub fn monolingual_all(&self) -> bool {
+        self.monolingual_all
+    }
+
+    /// Overrides the page language for labels, descriptions and texts
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    /// Show the uncertainty bounds of quantities
+    pub fn quantity_bounds(&self) -> bool {
+        self.quantity_bounds
+    }
+
+    /// Number of rows per page; further rows go to numbered subpages
+    pub fn per_page(&self) -> Option<usize> {
+        self.per_page
+    }
+
+    pub fn filters(&self) -> &Vec<RowFilter> {
+        &self.filters
+    }
+
+    pub fn item_var(&self) -> &str {
+        &self.item_var
+    }
+
     pub fn wikibase(&self) -> &str {
         &self.wikibase
     }
@@ -254,7 +563,7 @@ impl TemplateParams {
         self.wdedit
     }
 
-    pub fn sort(&self) -> &SortMode {
+    pub fn sort(&self) -> &Vec<SortCriterion> {
         &self.sort
     }
 
@@ -270,7 +579,19 @@ impl TemplateParams {
         self.min_section
     }
 
-    pub fn summary(&self) -> &Option<String> {
+    pub fn section_sort(&self) -> &SectionSort {
+        &self.section_sort
+    }
+
+    pub fn section_level(&self) -> usize {
+        self.section_level
+    }
+
+    pub fn section_misc(&self) -> Option<&str> {
+        self.section_misc.as_deref()
+    }
+
+    pub fn summary(&self) -> &Vec<SummaryType> {
         &self.summary
     }
 
@@ -289,8 +610,4 @@ impl TemplateParams {
     pub fn links(&self) -> &LinksType {
         &self.links
     }
-
-    pub fn set_links(&mut self, links: LinksType) {
-        self.links = links;
-    }
 }